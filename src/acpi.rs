@@ -0,0 +1,123 @@
+use crate::memory;
+use conquer_once::spin::OnceCell;
+use core::{mem, ptr};
+use multiboot2::BootInformation;
+use x86_64::PhysAddr;
+
+/// Header shared by all ACPI system description tables.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RootTable {
+    Rsdt(PhysAddr),
+    Xsdt(PhysAddr),
+}
+
+static ROOT_TABLE: OnceCell<Option<RootTable>> = OnceCell::uninit();
+
+/// Reads the root table address from the RSDP copy the bootloader passed us.
+///
+/// Must be called before the boot information is handed to the memory module.
+pub fn root_table(boot_info: &BootInformation) -> Option<RootTable> {
+    if let Some(rsdp) = boot_info.rsdp_v2_tag() {
+        if rsdp.checksum_is_valid() && rsdp.xsdt_address() != 0 {
+            return Some(RootTable::Xsdt(PhysAddr::new(rsdp.xsdt_address() as u64)));
+        }
+    }
+    boot_info
+        .rsdp_v1_tag()
+        .filter(|rsdp| rsdp.checksum_is_valid())
+        .map(|rsdp| RootTable::Rsdt(PhysAddr::new(rsdp.rsdt_address() as u64)))
+}
+
+/// Initializes table lookup. Falls back to searching the BIOS area for the
+/// RSDP if the bootloader did not provide one.
+///
+/// Requires the physical memory mapping of the memory module.
+pub fn init(root: Option<RootTable>) {
+    ROOT_TABLE.init_once(|| root.or_else(search_rsdp));
+}
+
+/// Returns the physical address of the first table with the given signature.
+pub fn find_table(signature: &[u8; 4]) -> Option<PhysAddr> {
+    let (root, entry_size) = match ROOT_TABLE.get().copied().flatten()? {
+        RootTable::Rsdt(addr) => (addr, 4),
+        RootTable::Xsdt(addr) => (addr, 8),
+    };
+    let header: SdtHeader = unsafe { read(root) };
+    let length = header.length as usize;
+    if length < mem::size_of::<SdtHeader>() || !checksum_is_valid(root, length) {
+        return None;
+    }
+    let entries = (length - mem::size_of::<SdtHeader>()) / entry_size;
+    let first_entry = root + mem::size_of::<SdtHeader>();
+
+    (0..entries)
+        .map(|i| {
+            let entry = first_entry + i * entry_size;
+            let address = if entry_size == 4 {
+                u64::from(unsafe { read::<u32>(entry) })
+            } else {
+                unsafe { read::<u64>(entry) }
+            };
+            PhysAddr::new(address)
+        })
+        .find(|&table| {
+            let header: SdtHeader = unsafe { read(table) };
+            header.signature == *signature && checksum_is_valid(table, header.length as usize)
+        })
+}
+
+/// Reads a `T` from physical memory.
+///
+/// # Safety
+///
+/// The caller has to make sure that a valid `T` is located at `addr`.
+pub unsafe fn read<T: Copy>(addr: PhysAddr) -> T {
+    ptr::read_unaligned(memory::phys_to_virt(addr).as_ptr())
+}
+
+fn checksum_is_valid(addr: PhysAddr, length: usize) -> bool {
+    (0..length)
+        .map(|i| unsafe { read::<u8>(addr + i) })
+        .fold(0u8, |sum, byte| sum.wrapping_add(byte))
+        == 0
+}
+
+fn search_rsdp() -> Option<RootTable> {
+    // the first KiB of the extended BIOS data area and the BIOS ROM
+    let ebda = PhysAddr::new(u64::from(unsafe { read::<u16>(PhysAddr::new(0x40e)) }) << 4);
+    let areas = [
+        (ebda, ebda + 1024u64),
+        (PhysAddr::new(0xe0000), PhysAddr::new(0x100000)),
+    ];
+
+    for (start, end) in areas {
+        let mut addr = start;
+        while addr < end {
+            if unsafe { read::<[u8; 8]>(addr) } == *b"RSD PTR " && checksum_is_valid(addr, 20) {
+                let revision: u8 = unsafe { read(addr + 15u64) };
+                let xsdt: u64 = unsafe { read(addr + 24u64) };
+                if revision >= 2 && xsdt != 0 {
+                    return Some(RootTable::Xsdt(PhysAddr::new(xsdt)));
+                }
+                let rsdt: u32 = unsafe { read(addr + 16u64) };
+                return Some(RootTable::Rsdt(PhysAddr::new(u64::from(rsdt))));
+            }
+            addr += 16u64;
+        }
+    }
+    None
+}
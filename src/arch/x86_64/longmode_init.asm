@@ -14,6 +14,9 @@ long_mode_start:
 
     
 
+    ; terminate the frame pointer chain for stack traces
+    xor rbp, rbp
+
     ; call the rust main
     extern kernel_main
     call kernel_main
@@ -0,0 +1,257 @@
+use crate::memory;
+use conquer_once::spin::OnceCell;
+use core::{arch::asm, fmt, slice, str};
+use multiboot2::{ElfSectionType, ElfSectionsTag};
+use x86_64::VirtAddr;
+
+/// Stop walking after this many frames, in case the chain is corrupted.
+const MAX_FRAMES: usize = 32;
+
+const SYMBOL_TYPE_FUNCTION: u8 = 2;
+
+/// An entry of the ELF64 symbol table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ElfSymbol {
+    name: u32,
+    info: u8,
+    other: u8,
+    section_index: u16,
+    value: u64,
+    size: u64,
+}
+
+struct SymbolTable {
+    symbols: &'static [ElfSymbol],
+    strings: &'static [u8],
+}
+
+/// A function symbol of the kernel.
+#[derive(Debug, Clone, Copy)]
+pub struct Symbol {
+    /// The mangled name, use [`Demangle`] to display it.
+    pub name: &'static str,
+    pub address: u64,
+}
+
+static SYMBOLS: OnceCell<SymbolTable> = OnceCell::uninit();
+
+/// Loads the kernel symbol table from the sections the bootloader loaded.
+///
+/// The kernel must not be stripped. Without a symbol table stack traces only
+/// show raw addresses.
+pub fn init(elf_sections_tag: &ElfSectionsTag) {
+    let symbols = elf_sections_tag
+        .sections()
+        .find(|s| s.section_type() == ElfSectionType::LinkerSymbolTable);
+    let strings = elf_sections_tag
+        .sections()
+        .find(|s| s.section_type() == ElfSectionType::StringTable && s.name() == ".strtab");
+    if let (Some(symbols), Some(strings)) = (symbols, strings) {
+        // the sections are loaded into the identity mapped first GiB
+        SYMBOLS.init_once(|| unsafe {
+            SymbolTable {
+                symbols: slice::from_raw_parts(
+                    symbols.start_address() as *const ElfSymbol,
+                    symbols.size() as usize / core::mem::size_of::<ElfSymbol>(),
+                ),
+                strings: slice::from_raw_parts(
+                    strings.start_address() as *const u8,
+                    strings.size() as usize,
+                ),
+            }
+        });
+    }
+}
+
+/// Finds the function containing `address`.
+pub fn symbolize(address: u64) -> Option<Symbol> {
+    let table = SYMBOLS.get()?;
+    let symbol = table.symbols.iter().find(|symbol| {
+        symbol.info & 0xf == SYMBOL_TYPE_FUNCTION
+            && symbol.value <= address
+            && address < symbol.value + symbol.size.max(1)
+    })?;
+    let name = table.strings.get(symbol.name as usize..)?;
+    let length = name.iter().position(|&c| c == 0)?;
+    Some(Symbol {
+        name: str::from_utf8(&name[..length]).ok()?,
+        address: symbol.value,
+    })
+}
+
+/// Writes a symbolized stack trace.
+///
+/// `ip` is shown as the first frame if given, the remaining frames are
+/// taken from the frame pointer chain starting at `frame_pointer`.
+pub fn write_trace(out: &mut impl fmt::Write, ip: Option<u64>, frame_pointer: u64) -> fmt::Result {
+    writeln!(out, "stack trace:")?;
+    // return addresses point behind the call, so look up the byte before
+    let frames = ip
+        .map(|ip| (ip, ip))
+        .into_iter()
+        .chain(walk(frame_pointer).map(|address| (address, address - 1)));
+    for (i, (address, lookup)) in frames.enumerate() {
+        match symbolize(lookup) {
+            Some(symbol) => writeln!(
+                out,
+                "  {i:2}: {address:#018x} {}+{:#x}",
+                Demangle(symbol.name),
+                address - symbol.address
+            )?,
+            None => writeln!(out, "  {i:2}: {address:#018x} <unknown>")?,
+        }
+    }
+    Ok(())
+}
+
+/// Displays a legacy mangled Rust symbol like `_ZN7trashos4init17h0123456789abcdefE`
+/// as `trashos::init`. Other symbols are shown unchanged.
+pub struct Demangle<'a>(pub &'a str);
+
+impl fmt::Display for Demangle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = match self.0.strip_prefix("_ZN").and_then(|s| s.strip_suffix('E')) {
+            Some(path) if Components(path).all(|c| c.is_some()) => path,
+            _ => return f.write_str(self.0),
+        };
+
+        let mut components = Components(path).flatten().peekable();
+        let mut first = true;
+        while let Some(component) = components.next() {
+            // skip the trailing hash
+            if components.peek().is_none() && is_hash(component) {
+                break;
+            }
+            if !first {
+                f.write_str("::")?;
+            }
+            first = false;
+            write_component(f, component)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over the length prefixed components of a mangled path.
+/// Yields `None` once if the path is malformed.
+struct Components<'a>(&'a str);
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Option<&'a str>;
+
+    fn next(&mut self) -> Option<Option<&'a str>> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let digits = self.0.bytes().take_while(u8::is_ascii_digit).count();
+        let component = self.0[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|length| self.0.get(digits..digits + length));
+        self.0 = match component {
+            Some(component) => &self.0[digits + component.len()..],
+            None => "",
+        };
+        Some(component)
+    }
+}
+
+fn is_hash(component: &str) -> bool {
+    component.len() == 17
+        && component.starts_with('h')
+        && component[1..].bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+fn write_component(f: &mut fmt::Formatter, mut component: &str) -> fmt::Result {
+    const ESCAPES: &[(&str, &str)] = &[
+        ("$SP$", "@"),
+        ("$BP$", "*"),
+        ("$RF$", "&"),
+        ("$LT$", "<"),
+        ("$GT$", ">"),
+        ("$LP$", "("),
+        ("$RP$", ")"),
+        ("$C$", ","),
+        ("$u20$", " "),
+        ("$u27$", "'"),
+        ("$u5b$", "["),
+        ("$u5d$", "]"),
+        ("$u7b$", "{"),
+        ("$u7d$", "}"),
+        ("$u7e$", "~"),
+        ("..", "::"),
+    ];
+    // a leading underscore escapes components starting with `$`
+    if component.starts_with("_$") {
+        component = &component[1..];
+    }
+    'outer: while !component.is_empty() {
+        for (escaped, replacement) in ESCAPES {
+            if let Some(rest) = component.strip_prefix(escaped) {
+                f.write_str(replacement)?;
+                component = rest;
+                continue 'outer;
+            }
+        }
+        let next = component[1..]
+            .find(|c| c == '$' || c == '.')
+            .map_or(component.len(), |i| i + 1);
+        f.write_str(&component[..next])?;
+        component = &component[next..];
+    }
+    Ok(())
+}
+
+/// Returns the frame pointer of the calling function.
+#[inline(always)]
+pub fn frame_pointer() -> u64 {
+    let rbp: u64;
+    unsafe { asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags)) };
+    rbp
+}
+
+/// Reads a word from the stack if the address is mapped.
+pub fn read_word(addr: u64) -> Option<u64> {
+    if addr % 8 != 0 {
+        return None;
+    }
+    let addr = VirtAddr::try_new(addr).ok()?;
+    memory::translate(addr)?;
+    Some(unsafe { *addr.as_ptr::<u64>() })
+}
+
+/// Iterator over the return addresses of a frame pointer chain.
+///
+/// Requires the kernel to be built with `-C force-frame-pointers=yes`.
+pub struct Frames {
+    frame_pointer: u64,
+    depth: usize,
+}
+
+impl Iterator for Frames {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        if self.frame_pointer == 0 || self.depth >= MAX_FRAMES {
+            return None;
+        }
+        let return_address = read_word(self.frame_pointer + 8)?;
+        let next = read_word(self.frame_pointer)?;
+        if return_address == 0 {
+            return None;
+        }
+        // the stack grows down, so the caller frames have to be above us
+        self.frame_pointer = if next > self.frame_pointer { next } else { 0 };
+        self.depth += 1;
+        Some(return_address)
+    }
+}
+
+/// Walks the stack starting at the frame `frame_pointer` points to.
+pub fn walk(frame_pointer: u64) -> Frames {
+    Frames {
+        frame_pointer,
+        depth: 0,
+    }
+}
@@ -0,0 +1,55 @@
+use crate::interrupts::stats;
+use crate::task::{executor, timer};
+use crate::vga::{self, Console};
+use crate::{input, memory, time};
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::time::Duration;
+use futures::stream::StreamExt;
+
+const REFRESH_PERIOD: Duration = Duration::from_secs(1);
+
+/// Keeps a live overview of the system on the diagnostics console.
+pub async fn run() {
+    let mut interval = timer::interval(REFRESH_PERIOD);
+    while interval.next().await.is_some() {
+        // build the page first, so it is drawn at once
+        let mut page = String::new();
+        write_page(&mut page).unwrap();
+        Console(vga::DIAGNOSTICS_CONSOLE).write_str(&page).unwrap();
+    }
+}
+
+fn write_page(out: &mut impl Write) -> fmt::Result {
+    write!(out, "\x1b[2J\x1b[H")?;
+    let uptime = time::uptime().as_secs();
+    writeln!(
+        out,
+        "up {}:{:02}:{:02}, time is {}\n",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60,
+        time::now()
+    )?;
+
+    let heap = memory::allocator::heap_stats();
+    let frames = memory::frame_stats();
+    writeln!(
+        out,
+        "heap {} of {} KiB, frames {} of {}",
+        heap.used / 1024,
+        heap.size / 1024,
+        frames.allocated,
+        frames.total
+    )?;
+    writeln!(
+        out,
+        "input {} events, {} dropped, {} subscribers",
+        input::published(),
+        input::dropped(),
+        input::subscribers()
+    )?;
+    writeln!(out, "tasks {}\n", executor::tasks().len())?;
+
+    stats::write_table(out)
+}
@@ -0,0 +1,149 @@
+use crate::sync::IrqSpinlock;
+use core::fmt::{self, Write};
+
+/// Bytes kept, the oldest lines are overwritten.
+const SIZE: usize = 16 * 1024;
+/// Bytes copied out per lock, so [`read`] keeps interrupts disabled only
+/// briefly.
+const CHUNK_SIZE: usize = 256;
+
+/// The kernel message buffer, filled by the log sink.
+struct Ring {
+    bytes: [u8; SIZE],
+    start: usize,
+    length: usize,
+    /// Bytes written since boot, the position after the newest byte.
+    written: u64,
+}
+
+static RING: IrqSpinlock<Ring> = IrqSpinlock::new(Ring {
+    bytes: [0; SIZE],
+    start: 0,
+    length: 0,
+    written: 0,
+});
+
+impl Ring {
+    fn push(&mut self, byte: u8) {
+        let end = (self.start + self.length) % SIZE;
+        self.bytes[end] = byte;
+        self.written += 1;
+        if self.length < SIZE {
+            self.length += 1;
+        } else {
+            self.start = (self.start + 1) % SIZE;
+        }
+    }
+
+    /// Position of the oldest byte kept.
+    fn oldest(&self) -> u64 {
+        self.written - self.length as u64
+    }
+
+    /// Copies the bytes from `position` up to `end` into `buffer`, skipping
+    /// those already overwritten. Returns the position of the first copied
+    /// byte and the number of bytes copied.
+    fn copy(&self, position: u64, end: u64, buffer: &mut [u8]) -> (u64, usize) {
+        let position = position.max(self.oldest());
+        let count = (end.saturating_sub(position) as usize).min(buffer.len());
+        let offset = (position - self.oldest()) as usize;
+        for (index, byte) in buffer[..count].iter_mut().enumerate() {
+            *byte = self.bytes[(self.start + offset + index) % SIZE];
+        }
+        (position, count)
+    }
+}
+
+/// Appends a line.
+pub fn write(line: &str) {
+    let mut ring = RING.lock();
+    for &byte in line.as_bytes().iter().chain(b"\n") {
+        ring.push(byte);
+    }
+}
+
+/// Writes the buffer, oldest line first.
+///
+/// Does not allocate. The buffer is copied out in small chunks and written
+/// with the lock released, lines logged meanwhile are not included.
+pub fn read(out: &mut impl Write) -> fmt::Result {
+    let (mut position, end) = {
+        let ring = RING.lock();
+        (ring.oldest(), ring.written)
+    };
+    let mut chunk = [0; CHUNK_SIZE];
+    while position < end {
+        let (start, count) = RING.lock().copy(position, end, &mut chunk);
+        if count == 0 {
+            break;
+        }
+        let last = start + count as u64 == end;
+        position = start + write_bytes(out, &chunk[..count], last)? as u64;
+    }
+    Ok(())
+}
+
+/// Writes the buffer while holding its lock, for crash reports after
+/// [`force_unlock`].
+pub fn read_locked(out: &mut impl Write) -> fmt::Result {
+    let ring = RING.lock();
+    let mut position = ring.oldest();
+    let mut chunk = [0; CHUNK_SIZE];
+    while position < ring.written {
+        let (start, count) = ring.copy(position, ring.written, &mut chunk);
+        let last = start + count as u64 == ring.written;
+        position = start + write_bytes(out, &chunk[..count], last)? as u64;
+    }
+    Ok(())
+}
+
+/// Releases the lock of the buffer, for crash reports.
+///
+/// # Safety
+///
+/// The code holding the lock must never run again.
+pub unsafe fn force_unlock() {
+    RING.force_unlock();
+}
+
+/// Number of bytes in the buffer.
+pub fn len() -> usize {
+    RING.lock().length
+}
+
+pub fn is_empty() -> bool {
+    len() == 0
+}
+
+pub fn clear() {
+    let mut ring = RING.lock();
+    ring.start = 0;
+    ring.length = 0;
+}
+
+/// Writes valid UTF-8 as is and replaces the rest, e.g. a character whose
+/// beginning was overwritten.
+///
+/// A character cut off at the end is left for the next call unless `last`
+/// is set. Returns the number of bytes written.
+fn write_bytes(out: &mut impl Write, bytes: &[u8], last: bool) -> Result<usize, fmt::Error> {
+    let mut rest = bytes;
+    loop {
+        match core::str::from_utf8(rest) {
+            Ok(text) => {
+                out.write_str(text)?;
+                return Ok(bytes.len());
+            }
+            Err(error) => {
+                let (valid, invalid) = rest.split_at(error.valid_up_to());
+                out.write_str(core::str::from_utf8(valid).unwrap())?;
+                match error.error_len() {
+                    Some(length) => rest = &invalid[length..],
+                    None if !last => return Ok(bytes.len() - invalid.len()),
+                    None => rest = &[],
+                }
+                out.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use crate::serial::{ComPort, EmergencyWriter};
+use crate::{dmesg, vga};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use x86_64::instructions::interrupts;
+
+/// Crashes so far, a second one happened while reporting the first.
+static CRASHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Takes over the outputs after a panic or fatal exception, which may have
+/// happened while their locks were held.
+///
+/// Returns the writer for the report. A crash while reporting a crash only
+/// gets serial output, after that nothing is reported anymore.
+pub fn enter() -> Option<Writer> {
+    interrupts::disable();
+    match CRASHES.fetch_add(1, Ordering::SeqCst) {
+        0 => {
+            // nothing else runs anymore, so the lock holders never continue
+            unsafe {
+                vga::force_unlock();
+                dmesg::force_unlock();
+            }
+            Some(Writer { console: true })
+        }
+        1 => {
+            let mut writer = Writer { console: false };
+            writeln!(writer, "\ncrashed while reporting a crash:").ok();
+            Some(writer)
+        }
+        _ => None,
+    }
+}
+
+/// Writes each piece to COM1 first, so it gets out even if the console
+/// crashes.
+pub struct Writer {
+    console: bool,
+}
+
+impl Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        EmergencyWriter(ComPort::Com1).write_str(s)?;
+        if self.console {
+            vga::Console(vga::SYSTEM_CONSOLE).write_str(s)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the kernel log to COM1.
+pub fn dump_dmesg() {
+    let mut serial = EmergencyWriter(ComPort::Com1);
+    writeln!(serial, "--- dmesg ---").ok();
+    dmesg::read_locked(&mut serial).ok();
+}
@@ -0,0 +1,155 @@
+use crate::ps2::{keyboard::KeyEvent, mouse::MouseEvent};
+use crate::serial::ComPort;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    ops::BitOr,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+use crossbeam::queue::ArrayQueue;
+use futures::{stream::Stream, task::AtomicWaker};
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Events a subscriber can buffer unless it asks for another capacity.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+    /// A byte received on a serial port.
+    Serial(ComPort, u8),
+}
+
+/// Kinds of events a subscriber receives, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter(u8);
+
+struct Subscriber {
+    filter: Filter,
+    queue: ArrayQueue<InputEvent>,
+    waker: AtomicWaker,
+    dropped: AtomicU64,
+}
+
+/// Only written with interrupts disabled, so publishing from an interrupt
+/// handler can never deadlock.
+static SUBSCRIBERS: RwLock<Vec<Arc<Subscriber>>> = RwLock::new(Vec::new());
+static PUBLISHED: AtomicU64 = AtomicU64::new(0);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Hands `event` to every subscriber.
+///
+/// Does not block or allocate, so drivers call it from their interrupt
+/// handlers. Subscribers that fall behind lose the newest events, which is
+/// counted instead of blocking the device.
+pub fn publish(event: InputEvent) {
+    PUBLISHED.fetch_add(1, Ordering::Relaxed);
+    for subscriber in SUBSCRIBERS.read().iter() {
+        if !subscriber.filter.matches(&event) {
+            continue;
+        }
+        if subscriber.queue.push(event).is_err() {
+            subscriber.dropped.fetch_add(1, Ordering::Relaxed);
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+        subscriber.waker.wake();
+    }
+}
+
+/// Number of events published since boot.
+pub fn published() -> u64 {
+    PUBLISHED.load(Ordering::Relaxed)
+}
+
+/// Number of events dropped by all subscribers since boot.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+pub fn subscribers() -> usize {
+    SUBSCRIBERS.read().len()
+}
+
+pub fn subscribe(filter: Filter) -> Subscription {
+    subscribe_with_capacity(filter, DEFAULT_CAPACITY)
+}
+
+/// Receives the events matching `filter` published from now on, until the
+/// subscription is dropped. Other events never take up room in its queue.
+pub fn subscribe_with_capacity(filter: Filter, capacity: usize) -> Subscription {
+    let subscriber = Arc::new(Subscriber {
+        filter,
+        queue: ArrayQueue::new(capacity),
+        waker: AtomicWaker::new(),
+        dropped: AtomicU64::new(0),
+    });
+    without_interrupts(|| SUBSCRIBERS.write().push(subscriber.clone()));
+    Subscription { subscriber }
+}
+
+pub struct Subscription {
+    subscriber: Arc<Subscriber>,
+}
+
+impl Filter {
+    pub const KEY: Filter = Filter(1 << 0);
+    pub const MOUSE: Filter = Filter(1 << 1);
+    pub const SERIAL: Filter = Filter(1 << 2);
+    pub const ALL: Filter = Filter(Filter::KEY.0 | Filter::MOUSE.0 | Filter::SERIAL.0);
+
+    pub fn matches(self, event: &InputEvent) -> bool {
+        let kind = match event {
+            InputEvent::Key(_) => Filter::KEY,
+            InputEvent::Mouse(_) => Filter::MOUSE,
+            InputEvent::Serial(..) => Filter::SERIAL,
+        };
+        self.0 & kind.0 != 0
+    }
+}
+
+impl BitOr for Filter {
+    type Output = Filter;
+
+    fn bitor(self, other: Filter) -> Filter {
+        Filter(self.0 | other.0)
+    }
+}
+
+impl Subscription {
+    /// Events this subscriber lost because its queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        without_interrupts(|| {
+            SUBSCRIBERS
+                .write()
+                .retain(|subscriber| !Arc::ptr_eq(subscriber, &self.subscriber))
+        });
+    }
+}
+
+impl Stream for Subscription {
+    type Item = InputEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<InputEvent>> {
+        let subscriber = &self.subscriber;
+        if let Some(event) = subscriber.queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+        subscriber.waker.register(cx.waker());
+        match subscriber.queue.pop() {
+            Some(event) => {
+                subscriber.waker.take();
+                Poll::Ready(Some(event))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use super::Nesting;
+use crate::{backtrace, emergency, gdt, hlt_loop, memory, serial, vga};
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::mem::{size_of, MaybeUninit};
+use core::ptr;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{
+    Entry, HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode,
+    SelectorErrorCode,
+};
+use x86_64::VirtAddr;
+
+/// Number of instruction bytes shown in a crash report.
+const INSTRUCTION_BYTES: u64 = 16;
+const EXCEPTION_VECTORS: usize = 32;
+
+/// General purpose registers of the interrupted code, except rsp and rbp
+/// which are part of the report anyway.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Registers {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+}
+
+/// Filled by the entry stubs, one slot per vector so a nested exception
+/// does not overwrite the registers of the one being reported.
+static mut REGISTERS: [Registers; EXCEPTION_VECTORS] = [Registers::ZERO; EXCEPTION_VECTORS];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    DivideError,
+    Debug,
+    NonMaskableInterrupt,
+    Breakpoint,
+    Overflow,
+    BoundRangeExceeded,
+    InvalidOpcode,
+    DeviceNotAvailable,
+    DoubleFault,
+    InvalidTss,
+    SegmentNotPresent,
+    StackSegmentFault,
+    GeneralProtectionFault,
+    PageFault,
+    X87FloatingPoint,
+    AlignmentCheck,
+    MachineCheck,
+    SimdFloatingPoint,
+    Virtualization,
+    ControlProtection,
+    HypervisorInjection,
+    VmmCommunication,
+    Security,
+}
+
+impl Exception {
+    pub fn vector(self) -> u8 {
+        match self {
+            Exception::DivideError => 0,
+            Exception::Debug => 1,
+            Exception::NonMaskableInterrupt => 2,
+            Exception::Breakpoint => 3,
+            Exception::Overflow => 4,
+            Exception::BoundRangeExceeded => 5,
+            Exception::InvalidOpcode => 6,
+            Exception::DeviceNotAvailable => 7,
+            Exception::DoubleFault => 8,
+            Exception::InvalidTss => 10,
+            Exception::SegmentNotPresent => 11,
+            Exception::StackSegmentFault => 12,
+            Exception::GeneralProtectionFault => 13,
+            Exception::PageFault => 14,
+            Exception::X87FloatingPoint => 16,
+            Exception::AlignmentCheck => 17,
+            Exception::MachineCheck => 18,
+            Exception::SimdFloatingPoint => 19,
+            Exception::Virtualization => 20,
+            Exception::ControlProtection => 21,
+            Exception::HypervisorInjection => 28,
+            Exception::VmmCommunication => 29,
+            Exception::Security => 30,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Exception::DivideError => "DIVIDE ERROR",
+            Exception::Debug => "DEBUG",
+            Exception::NonMaskableInterrupt => "NON-MASKABLE INTERRUPT",
+            Exception::Breakpoint => "BREAKPOINT",
+            Exception::Overflow => "OVERFLOW",
+            Exception::BoundRangeExceeded => "BOUND RANGE EXCEEDED",
+            Exception::InvalidOpcode => "INVALID OPCODE",
+            Exception::DeviceNotAvailable => "DEVICE NOT AVAILABLE",
+            Exception::DoubleFault => "DOUBLE FAULT",
+            Exception::InvalidTss => "INVALID TSS",
+            Exception::SegmentNotPresent => "SEGMENT NOT PRESENT",
+            Exception::StackSegmentFault => "STACK SEGMENT FAULT",
+            Exception::GeneralProtectionFault => "GENERAL PROTECTION FAULT",
+            Exception::PageFault => "PAGE FAULT",
+            Exception::X87FloatingPoint => "X87 FLOATING POINT",
+            Exception::AlignmentCheck => "ALIGNMENT CHECK",
+            Exception::MachineCheck => "MACHINE CHECK",
+            Exception::SimdFloatingPoint => "SIMD FLOATING POINT",
+            Exception::Virtualization => "VIRTUALIZATION",
+            Exception::ControlProtection => "CONTROL PROTECTION",
+            Exception::HypervisorInjection => "HYPERVISOR INJECTION",
+            Exception::VmmCommunication => "VMM COMMUNICATION",
+            Exception::Security => "SECURITY",
+        }
+    }
+}
+
+impl Registers {
+    const ZERO: Registers = Registers {
+        rax: 0,
+        rbx: 0,
+        rcx: 0,
+        rdx: 0,
+        rsi: 0,
+        rdi: 0,
+        r8: 0,
+        r9: 0,
+        r10: 0,
+        r11: 0,
+        r12: 0,
+        r13: 0,
+        r14: 0,
+        r15: 0,
+    };
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ErrorCode {
+    None,
+    Raw(u64),
+    Selector(SelectorErrorCode),
+    PageFault(PageFaultErrorCode),
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorCode::None => write!(f, "none"),
+            ErrorCode::Raw(code) => write!(f, "{code:#x}"),
+            ErrorCode::Selector(code) if code.is_null() => write!(f, "0 (no selector)"),
+            ErrorCode::Selector(code) => write!(
+                f,
+                "selector index {} in {:?}{}",
+                code.index(),
+                code.descriptor_table(),
+                if code.external() { ", external" } else { "" }
+            ),
+            ErrorCode::PageFault(code) => write!(f, "{code:?} ({:#b})", code.bits()),
+        }
+    }
+}
+
+/// Everything we know about an exception, printed in a fixed layout.
+pub struct CrashReport<'a> {
+    pub exception: Exception,
+    pub stack_frame: &'a InterruptStackFrame,
+    pub error_code: ErrorCode,
+    pub registers: Registers,
+    /// Frame pointer of the interrupted code.
+    pub frame_pointer: u64,
+}
+
+impl<'a> CrashReport<'a> {
+    /// Creates a report. `handler_frame` is the frame pointer of the handler
+    /// itself, which links to the frame of the interrupted code.
+    pub fn new(
+        exception: Exception,
+        stack_frame: &'a InterruptStackFrame,
+        error_code: ErrorCode,
+        handler_frame: u64,
+    ) -> Self {
+        CrashReport {
+            exception,
+            stack_frame,
+            error_code,
+            registers: unsafe { REGISTERS[usize::from(exception.vector())] },
+            frame_pointer: backtrace::read_word(handler_frame).unwrap_or(0),
+        }
+    }
+
+    /// Prints the report of an exception the kernel continues after.
+    ///
+    /// Outputs whose lock is held by the interrupted code are skipped, as
+    /// waiting for them would never end.
+    pub fn print(&self) {
+        self.write(&mut TryWriter).ok();
+    }
+
+    pub fn write(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let frame = self.stack_frame;
+        writeln!(
+            out,
+            "EXCEPTION: {} (vector {})",
+            self.exception.name(),
+            self.exception.vector()
+        )?;
+        writeln!(out, "error code: {}", self.error_code)?;
+        if let ErrorCode::PageFault(_) = self.error_code {
+            writeln!(out, "address: {:#x}", Cr2::read().as_u64())?;
+        }
+        writeln!(
+            out,
+            "rip {:#018x} cs {:#06x} rflags {:#010x}",
+            frame.instruction_pointer.as_u64(),
+            frame.code_segment,
+            frame.cpu_flags
+        )?;
+        writeln!(
+            out,
+            "rsp {:#018x} ss {:#06x} rbp {:#018x}",
+            frame.stack_pointer.as_u64(),
+            frame.stack_segment,
+            self.frame_pointer
+        )?;
+        let registers = &self.registers;
+        writeln!(
+            out,
+            "rax {:#018x} rbx {:#018x} rcx {:#018x}",
+            registers.rax, registers.rbx, registers.rcx
+        )?;
+        writeln!(
+            out,
+            "rdx {:#018x} rsi {:#018x} rdi {:#018x}",
+            registers.rdx, registers.rsi, registers.rdi
+        )?;
+        writeln!(
+            out,
+            "r8  {:#018x} r9  {:#018x} r10 {:#018x}",
+            registers.r8, registers.r9, registers.r10
+        )?;
+        writeln!(
+            out,
+            "r11 {:#018x} r12 {:#018x} r13 {:#018x}",
+            registers.r11, registers.r12, registers.r13
+        )?;
+        writeln!(
+            out,
+            "r14 {:#018x} r15 {:#018x}",
+            registers.r14, registers.r15
+        )?;
+        writeln!(
+            out,
+            "cr0 {:#010x} cr2 {:#018x} cr3 {:#018x} cr4 {:#010x}",
+            Cr0::read_raw(),
+            Cr2::read().as_u64(),
+            Cr3::read().0.start_address().as_u64(),
+            Cr4::read_raw()
+        )?;
+        self.write_instruction_bytes(out)?;
+        backtrace::write_trace(
+            out,
+            Some(self.stack_frame.instruction_pointer.as_u64()),
+            self.frame_pointer,
+        )
+    }
+
+    fn write_instruction_bytes(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let ip = self.stack_frame.instruction_pointer;
+        write!(out, "code:")?;
+        if memory::translate(ip).is_none()
+            || memory::translate(ip + (INSTRUCTION_BYTES - 1)).is_none()
+        {
+            return writeln!(out, " <not mapped>");
+        }
+        for i in 0..INSTRUCTION_BYTES {
+            let byte = unsafe { *(ip + i).as_ptr::<u8>() };
+            write!(out, " {byte:02x}")?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Writes to the system console and COM1, skipping each that is locked.
+struct TryWriter;
+
+impl Write for TryWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        serial::COM1.try_write_bytes(s.as_bytes());
+        if let Some(mut console) = vga::CONSOLES[vga::SYSTEM_CONSOLE].try_lock() {
+            console.write_string(s);
+        }
+        Ok(())
+    }
+}
+
+/// Reports the exception, even if the crashed code held the output locks,
+/// and stops the kernel.
+fn fatal(report: CrashReport) -> ! {
+    if let Some(mut out) = emergency::enter() {
+        report.write(&mut out).ok();
+        emergency::dump_dmesg();
+    }
+    hlt_loop();
+}
+
+/// Saves the general purpose registers for the report and jumps to the
+/// handler, leaving the stack as the CPU pushed it.
+macro_rules! entry_stub {
+    ($stub:ident, $vector:expr, $handler:ident) => {
+        #[naked]
+        unsafe extern "C" fn $stub() -> ! {
+            asm!(
+                "mov [rip + {registers} + {offset}], rax",
+                "mov [rip + {registers} + {offset} + 8], rbx",
+                "mov [rip + {registers} + {offset} + 16], rcx",
+                "mov [rip + {registers} + {offset} + 24], rdx",
+                "mov [rip + {registers} + {offset} + 32], rsi",
+                "mov [rip + {registers} + {offset} + 40], rdi",
+                "mov [rip + {registers} + {offset} + 48], r8",
+                "mov [rip + {registers} + {offset} + 56], r9",
+                "mov [rip + {registers} + {offset} + 64], r10",
+                "mov [rip + {registers} + {offset} + 72], r11",
+                "mov [rip + {registers} + {offset} + 80], r12",
+                "mov [rip + {registers} + {offset} + 88], r13",
+                "mov [rip + {registers} + {offset} + 96], r14",
+                "mov [rip + {registers} + {offset} + 104], r15",
+                "jmp {handler}",
+                registers = sym REGISTERS,
+                offset = const $vector * size_of::<Registers>(),
+                handler = sym $handler,
+                options(noreturn)
+            );
+        }
+    };
+}
+
+entry_stub!(divide_error_stub, 0, divide_error_handler);
+entry_stub!(debug_stub, 1, debug_handler);
+entry_stub!(
+    non_maskable_interrupt_stub,
+    2,
+    non_maskable_interrupt_handler
+);
+entry_stub!(breakpoint_stub, 3, breakpoint_handler);
+entry_stub!(overflow_stub, 4, overflow_handler);
+entry_stub!(bound_range_exceeded_stub, 5, bound_range_exceeded_handler);
+entry_stub!(invalid_opcode_stub, 6, invalid_opcode_handler);
+entry_stub!(device_not_available_stub, 7, device_not_available_handler);
+entry_stub!(double_fault_stub, 8, double_fault_handler);
+entry_stub!(invalid_tss_stub, 10, invalid_tss_handler);
+entry_stub!(segment_not_present_stub, 11, segment_not_present_handler);
+entry_stub!(stack_segment_fault_stub, 12, stack_segment_fault_handler);
+entry_stub!(
+    general_protection_fault_stub,
+    13,
+    general_protection_fault_handler
+);
+entry_stub!(page_fault_stub, 14, page_fault_handler);
+entry_stub!(x87_floating_point_stub, 16, x87_floating_point_handler);
+entry_stub!(alignment_check_stub, 17, alignment_check_handler);
+entry_stub!(machine_check_stub, 18, machine_check_handler);
+entry_stub!(simd_floating_point_stub, 19, simd_floating_point_handler);
+entry_stub!(virtualization_stub, 20, virtualization_handler);
+entry_stub!(control_protection_stub, 21, control_protection_handler);
+entry_stub!(hypervisor_injection_stub, 28, hypervisor_injection_handler);
+entry_stub!(vmm_communication_stub, 29, vmm_communication_handler);
+entry_stub!(security_stub, 30, security_handler);
+
+// The entries for #CP and #HV are private in the pinned `x86_64` version and
+// reached through the cast in `register`, check the layout it relies on.
+const _: () = {
+    const ENTRY: usize = size_of::<Entry<HandlerFunc>>();
+    let idt = MaybeUninit::<InterruptDescriptorTable>::uninit();
+    let base = idt.as_ptr();
+    // SAFETY: only the addresses of the fields are taken
+    let (virtualization, vmm_communication) = unsafe {
+        (
+            ptr::addr_of!((*base).virtualization).cast::<u8>(),
+            ptr::addr_of!((*base).vmm_communication_exception).cast::<u8>(),
+        )
+    };
+    let base = base.cast::<u8>();
+    assert!(size_of::<InterruptDescriptorTable>() == 256 * ENTRY);
+    assert!(unsafe { virtualization.offset_from(base) } == (20 * ENTRY) as isize);
+    assert!(unsafe { vmm_communication.offset_from(base) } == (29 * ENTRY) as isize);
+};
+
+fn stub_address(stub: unsafe extern "C" fn() -> !) -> VirtAddr {
+    VirtAddr::new(stub as usize as u64)
+}
+
+pub(super) fn register(idt: &mut InterruptDescriptorTable) {
+    // SAFETY: every stub jumps to a handler with the signature of its entry
+    unsafe {
+        idt.divide_error
+            .set_handler_addr(stub_address(divide_error_stub));
+        idt.debug.set_handler_addr(stub_address(debug_stub));
+        idt.non_maskable_interrupt
+            .set_handler_addr(stub_address(non_maskable_interrupt_stub));
+        idt.breakpoint
+            .set_handler_addr(stub_address(breakpoint_stub));
+        idt.overflow.set_handler_addr(stub_address(overflow_stub));
+        idt.bound_range_exceeded
+            .set_handler_addr(stub_address(bound_range_exceeded_stub));
+        idt.invalid_opcode
+            .set_handler_addr(stub_address(invalid_opcode_stub));
+        idt.device_not_available
+            .set_handler_addr(stub_address(device_not_available_stub));
+        idt.double_fault
+            .set_handler_addr(stub_address(double_fault_stub))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        idt.invalid_tss
+            .set_handler_addr(stub_address(invalid_tss_stub));
+        idt.segment_not_present
+            .set_handler_addr(stub_address(segment_not_present_stub));
+        idt.stack_segment_fault
+            .set_handler_addr(stub_address(stack_segment_fault_stub));
+        idt.general_protection_fault
+            .set_handler_addr(stub_address(general_protection_fault_stub));
+        idt.page_fault
+            .set_handler_addr(stub_address(page_fault_stub));
+        idt.x87_floating_point
+            .set_handler_addr(stub_address(x87_floating_point_stub));
+        idt.alignment_check
+            .set_handler_addr(stub_address(alignment_check_stub));
+        idt.machine_check
+            .set_handler_addr(stub_address(machine_check_stub));
+        idt.simd_floating_point
+            .set_handler_addr(stub_address(simd_floating_point_stub));
+        idt.virtualization
+            .set_handler_addr(stub_address(virtualization_stub));
+        idt.vmm_communication_exception
+            .set_handler_addr(stub_address(vmm_communication_stub));
+        idt.security_exception
+            .set_handler_addr(stub_address(security_stub));
+
+        // the x86_64 crate keeps the entries of the newer exceptions private,
+        // the table is a `repr(C)` array of 256 entries of the same layout,
+        // which is checked at compile time above
+        let entries =
+            &mut *(idt as *mut InterruptDescriptorTable as *mut [Entry<HandlerFunc>; 256]);
+        entries[Exception::ControlProtection.vector() as usize]
+            .set_handler_addr(stub_address(control_protection_stub));
+        entries[Exception::HypervisorInjection.vector() as usize]
+            .set_handler_addr(stub_address(hypervisor_injection_stub));
+    }
+}
+
+/// Defines handlers for exceptions without error code that can not be recovered from.
+macro_rules! fatal_handler {
+    ($name:ident, $exception:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame) {
+            let handler_frame = backtrace::frame_pointer();
+            let _nesting = Nesting::enter();
+            fatal(CrashReport::new(
+                $exception,
+                &stack_frame,
+                ErrorCode::None,
+                handler_frame,
+            ));
+        }
+    };
+}
+
+/// Defines handlers for exceptions with a segment selector error code.
+macro_rules! selector_handler {
+    ($name:ident, $exception:expr) => {
+        extern "x86-interrupt" fn $name(stack_frame: InterruptStackFrame, error_code: u64) {
+            let handler_frame = backtrace::frame_pointer();
+            let _nesting = Nesting::enter();
+            let error_code = ErrorCode::Selector(SelectorErrorCode::new_truncate(error_code));
+            fatal(CrashReport::new(
+                $exception,
+                &stack_frame,
+                error_code,
+                handler_frame,
+            ));
+        }
+    };
+}
+
+fatal_handler!(divide_error_handler, Exception::DivideError);
+fatal_handler!(
+    non_maskable_interrupt_handler,
+    Exception::NonMaskableInterrupt
+);
+fatal_handler!(overflow_handler, Exception::Overflow);
+fatal_handler!(bound_range_exceeded_handler, Exception::BoundRangeExceeded);
+fatal_handler!(invalid_opcode_handler, Exception::InvalidOpcode);
+fatal_handler!(device_not_available_handler, Exception::DeviceNotAvailable);
+fatal_handler!(x87_floating_point_handler, Exception::X87FloatingPoint);
+fatal_handler!(simd_floating_point_handler, Exception::SimdFloatingPoint);
+fatal_handler!(virtualization_handler, Exception::Virtualization);
+fatal_handler!(hypervisor_injection_handler, Exception::HypervisorInjection);
+
+selector_handler!(invalid_tss_handler, Exception::InvalidTss);
+selector_handler!(segment_not_present_handler, Exception::SegmentNotPresent);
+selector_handler!(stack_segment_fault_handler, Exception::StackSegmentFault);
+selector_handler!(
+    general_protection_fault_handler,
+    Exception::GeneralProtectionFault
+);
+
+extern "x86-interrupt" fn debug_handler(stack_frame: InterruptStackFrame) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    CrashReport::new(
+        Exception::Debug,
+        &stack_frame,
+        ErrorCode::None,
+        handler_frame,
+    )
+    .print();
+}
+
+extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    CrashReport::new(
+        Exception::Breakpoint,
+        &stack_frame,
+        ErrorCode::None,
+        handler_frame,
+    )
+    .print();
+}
+
+// TODO fix kernel Overflow
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) -> ! {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::DoubleFault,
+        &stack_frame,
+        ErrorCode::Raw(error_code),
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::PageFault,
+        &stack_frame,
+        ErrorCode::PageFault(error_code),
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn alignment_check_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::AlignmentCheck,
+        &stack_frame,
+        ErrorCode::Raw(error_code),
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn machine_check_handler(stack_frame: InterruptStackFrame) -> ! {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::MachineCheck,
+        &stack_frame,
+        ErrorCode::None,
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn vmm_communication_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::VmmCommunication,
+        &stack_frame,
+        ErrorCode::Raw(error_code),
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn control_protection_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: u64,
+) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::ControlProtection,
+        &stack_frame,
+        ErrorCode::Raw(error_code),
+        handler_frame,
+    ));
+}
+
+extern "x86-interrupt" fn security_handler(stack_frame: InterruptStackFrame, error_code: u64) {
+    let handler_frame = backtrace::frame_pointer();
+    let _nesting = Nesting::enter();
+    fatal(CrashReport::new(
+        Exception::Security,
+        &stack_frame,
+        ErrorCode::Raw(error_code),
+        handler_frame,
+    ));
+}
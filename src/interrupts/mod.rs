@@ -0,0 +1,292 @@
+use crate::sync::IrqSpinlock;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use spin::RwLock;
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+
+pub mod exceptions;
+pub mod stats;
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        exceptions::register(&mut idt);
+        set_dispatch_stubs!(
+            idt;
+            32, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47,
+            48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63
+        );
+        idt
+    };
+}
+
+/// TODO replace with apic
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// Vectors that handlers can be registered for at runtime.
+pub const DYNAMIC_VECTORS: core::ops::Range<u8> = 32..64;
+const DYNAMIC_VECTOR_COUNT: usize = 32;
+
+const PIC_1_COMMAND: u16 = 0x20;
+const PIC_2_COMMAND: u16 = 0xa0;
+/// OCW3 command to read the in-service register.
+const READ_ISR: u8 = 0x0b;
+
+pub static PICS: IrqSpinlock<ChainedPics> =
+    IrqSpinlock::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptVectors {
+    Timer = 32,
+    Keyboard,
+    Com2 = 35,
+    Com1,
+    Rtc = 40,
+    Mouse = 44,
+    Panic = 99,
+    Spurious = 255,
+}
+
+impl InterruptVectors {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+
+    /// The PIC line raising this vector.
+    pub fn irq(self) -> u8 {
+        self.as_u8() - PIC_1_OFFSET
+    }
+}
+
+/// Tells the dispatcher whether a handler took care of the interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqReturn {
+    Handled,
+    NotHandled,
+}
+
+/// Handlers run in interrupt context and get the vector that fired, so they
+/// must not block or allocate.
+pub type Handler = fn(u8) -> IrqReturn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandlerId {
+    vector: u8,
+    id: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// The vector is reserved for exceptions or has a fixed handler.
+    InvalidVector,
+}
+
+struct Registration {
+    id: u64,
+    name: &'static str,
+    handler: Handler,
+}
+
+/// The handlers of every dynamic vector, called in registration order.
+///
+/// Only written with interrupts disabled, so the dispatcher can never
+/// interrupt a writer.
+static HANDLERS: [RwLock<Vec<Registration>>; DYNAMIC_VECTOR_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: RwLock<Vec<Registration>> = RwLock::new(Vec::new());
+    [EMPTY; DYNAMIC_VECTOR_COUNT]
+};
+static NEXT_HANDLER_ID: AtomicU64 = AtomicU64::new(0);
+/// Depth of the interrupt handlers currently running.
+static NESTING: AtomicUsize = AtomicUsize::new(0);
+
+pub fn init() {
+    IDT.load();
+    let mut pics = PICS.lock();
+    unsafe {
+        pics.initialize();
+        // lines are unmasked once a handler is registered, except for the cascade
+        pics.write_masks(!(1 << 2), 0xff);
+    }
+}
+
+/// Registers `handler` for `vector`. Handlers of a shared vector are all
+/// called, in the order they were registered.
+pub fn register_handler(
+    vector: u8,
+    name: &'static str,
+    handler: Handler,
+) -> Result<HandlerId, RegisterError> {
+    let index = dynamic_index(vector).ok_or(RegisterError::InvalidVector)?;
+    let id = NEXT_HANDLER_ID.fetch_add(1, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        HANDLERS[index]
+            .write()
+            .push(Registration { id, name, handler });
+    });
+    Ok(HandlerId { vector, id })
+}
+
+/// Registers `handler` for a PIC line and unmasks it.
+pub fn register_irq(
+    irq: u8,
+    name: &'static str,
+    handler: Handler,
+) -> Result<HandlerId, RegisterError> {
+    if irq >= 16 {
+        return Err(RegisterError::InvalidVector);
+    }
+    let id = register_handler(PIC_1_OFFSET + irq, name, handler)?;
+    unmask_irq(irq);
+    Ok(id)
+}
+
+/// Removes a handler. PIC lines without handlers are masked again.
+pub fn unregister_handler(handler: HandlerId) -> bool {
+    let index = match dynamic_index(handler.vector) {
+        Some(index) => index,
+        None => return false,
+    };
+    let (removed, empty) = interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS[index].write();
+        let count = handlers.len();
+        handlers.retain(|registration| registration.id != handler.id);
+        (handlers.len() != count, handlers.is_empty())
+    });
+    let irq = handler.vector.wrapping_sub(PIC_1_OFFSET);
+    if removed && empty && irq < 16 {
+        mask_irq(irq);
+    }
+    removed
+}
+
+/// Names of the handlers registered for `vector`.
+pub fn handler_names(vector: u8) -> Vec<&'static str> {
+    dynamic_index(vector).map_or_else(Vec::new, |index| {
+        interrupts::without_interrupts(|| {
+            HANDLERS[index]
+                .read()
+                .iter()
+                .map(|registration| registration.name)
+                .collect()
+        })
+    })
+}
+
+/// Whether the caller runs inside an interrupt or exception handler.
+pub fn in_interrupt() -> bool {
+    NESTING.load(Ordering::Relaxed) != 0
+}
+
+/// Counts as interrupt context for [`in_interrupt`] while it lives.
+pub(super) struct Nesting;
+
+impl Nesting {
+    pub(super) fn enter() -> Nesting {
+        NESTING.fetch_add(1, Ordering::Relaxed);
+        Nesting
+    }
+}
+
+impl Drop for Nesting {
+    fn drop(&mut self) {
+        NESTING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Enables the given PIC line.
+pub fn unmask_irq(irq: u8) {
+    set_irq_mask(irq, false);
+}
+
+/// Disables the given PIC line.
+pub fn mask_irq(irq: u8) {
+    set_irq_mask(irq, true);
+}
+
+fn set_irq_mask(irq: u8, masked: bool) {
+    let mut pics = PICS.lock();
+    let mut masks = unsafe { pics.read_masks() };
+    let (pic, line) = if irq < 8 { (0, irq) } else { (1, irq - 8) };
+    if masked {
+        masks[pic] |= 1 << line;
+    } else {
+        masks[pic] &= !(1 << line);
+    }
+    unsafe { pics.write_masks(masks[0], masks[1]) };
+}
+
+fn dynamic_index(vector: u8) -> Option<usize> {
+    DYNAMIC_VECTORS
+        .contains(&vector)
+        .then(|| usize::from(vector - DYNAMIC_VECTORS.start))
+}
+
+fn end_of_interrupt(vector: u8) {
+    unsafe { PICS.lock().notify_end_of_interrupt(vector) };
+}
+
+/// Checks whether IRQ 7 or 15 was raised although no interrupt is in service.
+///
+/// A spurious IRQ 15 still has to be acknowledged at the master, which saw a
+/// real cascade interrupt.
+fn is_spurious(vector: u8) -> bool {
+    let command = match vector {
+        v if v == PIC_1_OFFSET + 7 => PIC_1_COMMAND,
+        v if v == PIC_2_OFFSET + 7 => PIC_2_COMMAND,
+        _ => return false,
+    };
+    let mut port = Port::<u8>::new(command);
+    let in_service = unsafe {
+        port.write(READ_ISR);
+        port.read()
+    };
+    if in_service & (1 << 7) != 0 {
+        return false;
+    }
+    stats::record_spurious_irq(vector - PIC_1_OFFSET);
+    if command == PIC_2_COMMAND {
+        end_of_interrupt(PIC_1_OFFSET + 2);
+    }
+    true
+}
+
+fn dispatch(vector: u8) {
+    if is_spurious(vector) {
+        return;
+    }
+    let index = usize::from(vector - DYNAMIC_VECTORS.start);
+    let start = stats::timestamp();
+
+    let nesting = Nesting::enter();
+    let mut handled = false;
+    for registration in HANDLERS[index].read().iter() {
+        if (registration.handler)(vector) == IrqReturn::Handled {
+            handled = true;
+        }
+    }
+    drop(nesting);
+    stats::record(index, start, handled);
+    end_of_interrupt(vector);
+}
+
+extern "x86-interrupt" fn dispatch_stub<const VECTOR: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(VECTOR);
+}
+
+/// Points the given vectors to `dispatch`.
+macro_rules! set_dispatch_stubs {
+    ($idt:ident; $($vector:literal),*) => {
+        $($idt[$vector].set_handler_fn(dispatch_stub::<$vector>);)*
+    };
+}
+use set_dispatch_stubs;
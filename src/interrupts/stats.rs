@@ -0,0 +1,154 @@
+use super::{DYNAMIC_VECTORS, DYNAMIC_VECTOR_COUNT};
+use crate::serial;
+use core::arch::x86_64::_rdtsc;
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of a single vector. Handler times are measured in TSC cycles.
+struct Counters {
+    count: AtomicU64,
+    unhandled: AtomicU64,
+    total_cycles: AtomicU64,
+    min_cycles: AtomicU64,
+    max_cycles: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            count: AtomicU64::new(0),
+            unhandled: AtomicU64::new(0),
+            total_cycles: AtomicU64::new(0),
+            min_cycles: AtomicU64::new(u64::MAX),
+            max_cycles: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A snapshot of the statistics of a vector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VectorStats {
+    pub vector: u8,
+    pub count: u64,
+    /// Interrupts no handler claimed.
+    pub unhandled: u64,
+    pub min_cycles: u64,
+    pub max_cycles: u64,
+    pub avg_cycles: u64,
+}
+
+/// Spurious interrupts of the PICs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpuriousStats {
+    pub irq7: u64,
+    pub irq15: u64,
+}
+
+static COUNTERS: [Counters; DYNAMIC_VECTOR_COUNT] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const NEW: Counters = Counters::new();
+    [NEW; DYNAMIC_VECTOR_COUNT]
+};
+static SPURIOUS_IRQ7: AtomicU64 = AtomicU64::new(0);
+static SPURIOUS_IRQ15: AtomicU64 = AtomicU64::new(0);
+
+pub(super) fn timestamp() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+pub(super) fn record(index: usize, start: u64, handled: bool) {
+    let cycles = timestamp().wrapping_sub(start);
+    let counters = &COUNTERS[index];
+    counters.count.fetch_add(1, Ordering::Relaxed);
+    if !handled {
+        counters.unhandled.fetch_add(1, Ordering::Relaxed);
+    }
+    counters.total_cycles.fetch_add(cycles, Ordering::Relaxed);
+    counters.min_cycles.fetch_min(cycles, Ordering::Relaxed);
+    counters.max_cycles.fetch_max(cycles, Ordering::Relaxed);
+}
+
+pub(super) fn record_spurious_irq(irq: u8) {
+    match irq {
+        7 => SPURIOUS_IRQ7.fetch_add(1, Ordering::Relaxed),
+        _ => SPURIOUS_IRQ15.fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+/// Statistics of a vector that handlers can be registered for.
+pub fn get(vector: u8) -> Option<VectorStats> {
+    let counters = &COUNTERS[super::dynamic_index(vector)?];
+    let count = counters.count.load(Ordering::Relaxed);
+    let min_cycles = counters.min_cycles.load(Ordering::Relaxed);
+    Some(VectorStats {
+        vector,
+        count,
+        unhandled: counters.unhandled.load(Ordering::Relaxed),
+        min_cycles: if count == 0 { 0 } else { min_cycles },
+        max_cycles: counters.max_cycles.load(Ordering::Relaxed),
+        avg_cycles: counters
+            .total_cycles
+            .load(Ordering::Relaxed)
+            .checked_div(count)
+            .unwrap_or(0),
+    })
+}
+
+/// Statistics of all vectors that fired at least once.
+pub fn active() -> impl Iterator<Item = VectorStats> {
+    DYNAMIC_VECTORS
+        .filter_map(get)
+        .filter(|stats| stats.count > 0)
+}
+
+pub fn spurious() -> SpuriousStats {
+    SpuriousStats {
+        irq7: SPURIOUS_IRQ7.load(Ordering::Relaxed),
+        irq15: SPURIOUS_IRQ15.load(Ordering::Relaxed),
+    }
+}
+
+/// Resets all counters.
+pub fn reset() {
+    for counters in COUNTERS.iter() {
+        counters.count.store(0, Ordering::Relaxed);
+        counters.unhandled.store(0, Ordering::Relaxed);
+        counters.total_cycles.store(0, Ordering::Relaxed);
+        counters.min_cycles.store(u64::MAX, Ordering::Relaxed);
+        counters.max_cycles.store(0, Ordering::Relaxed);
+    }
+    SPURIOUS_IRQ7.store(0, Ordering::Relaxed);
+    SPURIOUS_IRQ15.store(0, Ordering::Relaxed);
+}
+
+/// Writes the statistics as a table.
+pub fn write_table(out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "vector      count  unhandled  min cycles  avg cycles  max cycles  handlers"
+    )?;
+    for stats in active() {
+        writeln!(
+            out,
+            "{:6} {:10} {:10} {:11} {:11} {:11}  {}",
+            stats.vector,
+            stats.count,
+            stats.unhandled,
+            stats.min_cycles,
+            stats.avg_cycles,
+            stats.max_cycles,
+            super::handler_names(stats.vector).join(", ")
+        )?;
+    }
+    let spurious = spurious();
+    writeln!(
+        out,
+        "spurious: irq7 {} irq15 {}",
+        spurious.irq7, spurious.irq15
+    )
+}
+
+/// Prints the statistics to the serial port.
+pub fn dump() {
+    write_table(&mut serial::Writer(&serial::COM1)).expect("Printing to serial failed");
+}
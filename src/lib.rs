@@ -1,18 +1,35 @@
 #![no_std]
 #![no_main]
 #![feature(abi_x86_interrupt)]
+#![feature(asm_const)]
+#![feature(asm_sym)]
 #![feature(alloc_error_handler)]
 #![feature(const_mut_refs)]
+#![feature(const_ptr_offset_from)]
+#![feature(naked_functions)]
 
+use core::fmt::Write;
 use core::panic::PanicInfo;
-use task::{executor::Executor, keyboard, Task};
+use task::{executor::Executor, Priority, Task};
 use x86_64::addr::PhysAddr;
 extern crate alloc;
+pub mod acpi;
+pub mod backtrace;
+pub mod diagnostics;
+pub mod dmesg;
+pub mod emergency;
 pub mod gdt;
+pub mod input;
 pub mod interrupts;
+pub mod logging;
 pub mod memory;
+pub mod power;
+pub mod ps2;
 pub mod serial;
+pub mod shell;
+pub mod sync;
 pub mod task;
+pub mod time;
 pub mod vga;
 
 #[macro_export]
@@ -57,11 +74,13 @@ pub fn hlt_loop() -> ! {
 }
 
 fn init(multiboot_info_ptr: usize) {
+    logging::init();
     let boot_info =
         unsafe { multiboot2::load(multiboot_info_ptr).expect("Multiboot not present!") };
     let elf_sections_tag = boot_info
         .elf_sections_tag()
         .expect("Elf-sections tag required");
+    backtrace::init(&elf_sections_tag);
 
     let kernel_start = PhysAddr::new(
         elf_sections_tag
@@ -79,16 +98,14 @@ fn init(multiboot_info_ptr: usize) {
     );
     let multiboot_start = PhysAddr::new(multiboot_info_ptr.try_into().unwrap());
     let multiboot_end = multiboot_start + boot_info.total_size();
+    let acpi_root = acpi::root_table(&boot_info);
 
-    cprint!("init gdt ");
     gdt::init();
-    cprintln!("[done]");
+    log::info!("gdt initialized");
 
-    cprint!("init interrupts ");
     interrupts::init();
-    cprintln!("[done]");
+    log::info!("interrupts initialized");
 
-    cprint!("init memory ");
     memory::init(
         kernel_start,
         kernel_end,
@@ -96,22 +113,40 @@ fn init(multiboot_info_ptr: usize) {
         multiboot_end,
         boot_info,
     );
-    cprintln!("[done]");
+    log::info!("memory initialized");
 
-    x86_64::instructions::interrupts::enable();
-}
+    task::deferred::init();
 
+    serial::init();
+    log::info!("serial initialized");
 
+    acpi::init(acpi_root);
+    log::info!("acpi initialized");
 
-async fn count(start: u64) {
-    let mut count = start;
-    loop {
-        task::timer::sleep((50+start).try_into().unwrap()).await;
-        println!("{count}");
-        count += 2;
+    time::init();
+    log::info!("clocksource {}", time::clocksource().name());
+    log::info!("time is {}", time::now());
+
+    match ps2::init() {
+        Ok(_) => log::info!("ps/2 controller initialized"),
+        Err(error) => log::error!("ps/2 controller failed: {error:?}"),
     }
+
+    match ps2::keyboard::init() {
+        Ok(set) => log::info!("keyboard uses scancode set {set:?}"),
+        Err(error) => log::warn!("keyboard failed: {error:?}"),
+    }
+
+    match ps2::mouse::init() {
+        Ok(kind) => log::info!("mouse is {kind:?}"),
+        Err(error) => log::warn!("mouse failed: {error:?}"),
+    }
+
+    x86_64::instructions::interrupts::enable();
 }
 
+
+
 #[no_mangle]
 pub extern "C" fn kernel_main(multiboot_info_ptr: usize) {
     init(multiboot_info_ptr);
@@ -123,16 +158,25 @@ pub extern "C" fn kernel_main(multiboot_info_ptr: usize) {
     println!(" ");
 
     let mut executor = Executor::new();
-    executor.spawn(Task::new(task::timer::indicator()));
-    executor.spawn(Task::new(keyboard::print_keypresses()));
-    executor.spawn(Task::new(count(0)));
-    executor.spawn(Task::new(count(1)));
+    executor.spawn(Task::named("indicator", task::timer::indicator()));
+    executor
+        .spawn(Task::named("consoles", vga::handle_keys()).with_priority(Priority::Interactive));
+    executor
+        .spawn(Task::named("diagnostics", diagnostics::run()).with_priority(Priority::Background));
+    executor.spawn(Task::named("shell", shell::run()).with_priority(Priority::Interactive));
     executor.run();
 }
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    x86_64::instructions::interrupts::disable();
-    cprintln!("{info}");
+    if let Some(mut out) = emergency::enter() {
+        writeln!(out, "{info}").ok();
+        if let Some((id, name)) = task::executor::current() {
+            let name = name.unwrap_or("unnamed");
+            writeln!(out, "in task {id} ({name})").ok();
+        }
+        backtrace::write_trace(&mut out, None, backtrace::frame_pointer()).ok();
+        emergency::dump_dmesg();
+    }
     hlt_loop();
 }
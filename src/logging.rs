@@ -0,0 +1,304 @@
+use crate::interrupts;
+use crate::sync::IrqSpinlock;
+use crate::task::{deferred, timer};
+use crate::{dmesg, serial, time, vga};
+use alloc::{string::String, vec::Vec};
+use core::{
+    fmt::{self, Write},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+use crossbeam::atomic::AtomicCell;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+/// Longer lines are cut off.
+const LINE_SIZE: usize = 160;
+/// Records logged in interrupt context that can wait for the executor.
+const PENDING_SIZE: usize = 32;
+const MAX_SINKS: usize = 8;
+
+/// Receives every formatted log line the filters let through.
+///
+/// Sinks are only called in task context, records logged by interrupt
+/// handlers are written once the executor runs the deferred work.
+pub trait Sink: Sync {
+    fn write(&self, level: Level, line: &str);
+}
+
+/// Writes to the log console.
+pub struct VgaSink;
+/// Writes to COM1.
+pub struct SerialSink;
+/// Keeps the lines in the [`dmesg`] buffer.
+pub struct DmesgSink;
+
+struct Registration {
+    sink: &'static dyn Sink,
+    level: LevelFilter,
+}
+
+/// A formatted record, built without allocating.
+#[derive(Clone, Copy)]
+struct Line {
+    level: Level,
+    bytes: [u8; LINE_SIZE],
+    length: usize,
+}
+
+/// Lines logged in interrupt context, waiting for the executor.
+struct Pending {
+    lines: [Line; PENDING_SIZE],
+    start: usize,
+    length: usize,
+}
+
+struct Logger;
+
+static LOGGER: Logger = Logger;
+static DEFAULT_LEVEL: AtomicCell<LevelFilter> = AtomicCell::new(LevelFilter::Info);
+/// Levels of modules and their submodules, the longest match wins.
+///
+/// The locks below are only written with interrupts disabled, so records can
+/// be filtered in interrupt handlers.
+static FILTERS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+static SINKS: RwLock<[Option<Registration>; MAX_SINKS]> = {
+    const NONE: Option<Registration> = None;
+    RwLock::new([NONE; MAX_SINKS])
+};
+static PENDING: IrqSpinlock<Pending> = IrqSpinlock::new(Pending {
+    lines: [Line::EMPTY; PENDING_SIZE],
+    start: 0,
+    length: 0,
+});
+static FLUSH_SCHEDULED: AtomicBool = AtomicBool::new(false);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Installs the logger with the console, serial and dmesg sinks.
+///
+/// Needs no heap, so it runs first and catches all boot messages.
+pub fn init() {
+    add_sink(&VgaSink, LevelFilter::Trace);
+    add_sink(&SerialSink, LevelFilter::Trace);
+    add_sink(&DmesgSink, LevelFilter::Trace);
+    log::set_logger(&LOGGER).expect("a logger is already installed");
+    log::set_max_level(LevelFilter::Trace);
+}
+
+/// Adds a sink receiving the records up to `level`. Returns `false` if all
+/// slots are taken.
+pub fn add_sink(sink: &'static dyn Sink, level: LevelFilter) -> bool {
+    without_interrupts(|| {
+        let mut sinks = SINKS.write();
+        match sinks.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(Registration { sink, level });
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+pub fn default_level() -> LevelFilter {
+    DEFAULT_LEVEL.load()
+}
+
+/// Sets the level of records from modules that have none of their own.
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level);
+}
+
+/// Sets the level of `module` and its submodules, e.g. `trashos::ps2`.
+pub fn set_level(module: &str, level: LevelFilter) {
+    without_interrupts(|| {
+        let mut filters = FILTERS.write();
+        match filters.iter_mut().find(|(name, _)| name == module) {
+            Some(filter) => filter.1 = level,
+            None => filters.push((String::from(module), level)),
+        }
+    });
+}
+
+/// The levels set with [`set_level`].
+pub fn module_levels() -> Vec<(String, LevelFilter)> {
+    without_interrupts(|| FILTERS.read().clone())
+}
+
+/// The level applied to records of `target`.
+pub fn level(target: &str) -> LevelFilter {
+    FILTERS
+        .read()
+        .iter()
+        .filter(|(module, _)| {
+            target
+                .strip_prefix(module.as_str())
+                .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(module, _)| module.len())
+        .map_or_else(|| DEFAULT_LEVEL.load(), |(_, level)| *level)
+}
+
+/// Records lost because they were logged in interrupt context faster than
+/// the executor wrote them.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = Line::format(record);
+        if !interrupts::in_interrupt() {
+            flush();
+            line.write_to_sinks();
+            return;
+        }
+        // the interrupted code may hold the locks of the sinks
+        if !PENDING.lock().push(line) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        } else if !FLUSH_SCHEDULED.swap(true, Ordering::AcqRel)
+            && !deferred::defer_call(|_| flush(), 0)
+        {
+            // try again with the next record
+            FLUSH_SCHEDULED.store(false, Ordering::Release);
+        }
+    }
+
+    fn flush(&self) {
+        if !interrupts::in_interrupt() {
+            flush();
+        }
+    }
+}
+
+/// Writes the records queued by interrupt handlers.
+fn flush() {
+    FLUSH_SCHEDULED.store(false, Ordering::Release);
+    while let Some(line) = PENDING.lock().pop() {
+        line.write_to_sinks();
+    }
+}
+
+impl Pending {
+    fn push(&mut self, line: Line) -> bool {
+        if self.length == PENDING_SIZE {
+            return false;
+        }
+        self.lines[(self.start + self.length) % PENDING_SIZE] = line;
+        self.length += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<Line> {
+        if self.length == 0 {
+            return None;
+        }
+        let line = self.lines[self.start];
+        self.start = (self.start + 1) % PENDING_SIZE;
+        self.length -= 1;
+        Some(line)
+    }
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        level: Level::Trace,
+        bytes: [0; LINE_SIZE],
+        length: 0,
+    };
+
+    fn format(record: &Record) -> Line {
+        let mut line = Line {
+            level: record.level(),
+            ..Line::EMPTY
+        };
+        let uptime = time::ticks_to_duration(timer::ticks());
+        // a cut off line is still worth logging
+        write!(
+            line,
+            "[{:5}.{:03}] {:<5} {}: {}",
+            uptime.as_secs(),
+            uptime.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        )
+        .ok();
+        line
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole characters are copied
+        core::str::from_utf8(&self.bytes[..self.length]).unwrap_or_default()
+    }
+
+    fn write_to_sinks(&self) {
+        for registration in SINKS.read().iter().flatten() {
+            if self.level <= registration.level {
+                registration.sink.write(self.level, self.as_str());
+            }
+        }
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let space = LINE_SIZE - self.length;
+        let mut count = s.len().min(space);
+        while !s.is_char_boundary(count) {
+            count -= 1;
+        }
+        self.bytes[self.length..self.length + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.length += count;
+        if count < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// ANSI color of the level, understood by the console and serial terminals.
+fn color(level: Level) -> &'static str {
+    match level {
+        Level::Error => "\x1b[1;31m",
+        Level::Warn => "\x1b[1;33m",
+        Level::Info => "",
+        Level::Debug | Level::Trace => "\x1b[37m",
+    }
+}
+
+impl Sink for VgaSink {
+    fn write(&self, level: Level, line: &str) {
+        writeln!(
+            vga::Console(vga::LOG_CONSOLE),
+            "{}{line}\x1b[0m",
+            color(level)
+        )
+        .ok();
+    }
+}
+
+impl Sink for SerialSink {
+    fn write(&self, level: Level, line: &str) {
+        writeln!(
+            serial::Writer(&serial::COM1),
+            "{}{line}\x1b[0m",
+            color(level)
+        )
+        .ok();
+    }
+}
+
+impl Sink for DmesgSink {
+    fn write(&self, _level: Level, line: &str) {
+        dmesg::write(line);
+    }
+}
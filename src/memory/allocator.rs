@@ -5,7 +5,7 @@ use x86_64::{
     VirtAddr,
 };
 
-use super::block_allocator::FixedSizeBlockAllocator;
+use super::block_allocator::{FixedSizeBlockAllocator, HeapStats};
 
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
@@ -55,6 +55,10 @@ pub fn init_heap(
     Ok(())
 }
 
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("allocation error: {:?}", layout)
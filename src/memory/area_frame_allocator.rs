@@ -9,8 +9,13 @@ pub struct AreaFrameAllocator {
     info: BootInformation,
     kernel: PhysFrameRangeInclusive,
     multiboot: PhysFrameRangeInclusive,
+    allocated: usize,
 }
 
+// `BootInformation` only holds a pointer to the multiboot structure, which
+// stays valid and unmodified for the whole runtime of the kernel.
+unsafe impl Send for AreaFrameAllocator {}
+
 impl AreaFrameAllocator {
     pub fn new(
         kernel_start: PhysAddr,
@@ -29,11 +34,27 @@ impl AreaFrameAllocator {
             info,
             kernel: PhysFrame::range_inclusive(kernel_start, kernel_end),
             multiboot: PhysFrame::range_inclusive(multiboot_start, multiboot_end),
+            allocated: 0,
         };
         allocator.choose_next_area();
         allocator
     }
 
+    /// Number of frames handed out so far.
+    pub fn allocated_frames(&self) -> usize {
+        self.allocated
+    }
+
+    /// Number of frames in the available memory areas, 0 if the boot info has
+    /// no memory map.
+    pub fn total_frames(&self) -> usize {
+        self.info.memory_map_tag().map_or(0, |tag| {
+            tag.memory_areas()
+                .map(|area| (area.size() / 4096) as usize)
+                .sum()
+        })
+    }
+
     fn choose_next_area(&mut self) {
         let next_area = self
             .info
@@ -63,7 +84,7 @@ impl AreaFrameAllocator {
 unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
         if let Some(area) = self.current_area {
-            let frame = self.next_free_frame.clone();
+            let frame = self.next_free_frame;
             // the last frame of the current area
             let current_area_last_frame = area.end;
             if frame > current_area_last_frame {
@@ -90,6 +111,7 @@ unsafe impl FrameAllocator<Size4KiB> for AreaFrameAllocator {
                     self.next_free_frame.start_address() + self.next_free_frame.size(),
                 )
                 .unwrap();
+                self.allocated += 1;
                 return Some(frame);
             }
             // `frame` was not valid, try it again with the updated `next_free_frame`
@@ -19,6 +19,17 @@ pub struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    allocated: usize,
+}
+
+/// Usage of the kernel heap in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    /// Bytes taken from the heap, including free blocks kept for reuse.
+    pub used: usize,
+    /// Bytes currently allocated.
+    pub allocated: usize,
 }
 
 impl FixedSizeBlockAllocator {
@@ -28,6 +39,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            allocated: 0,
         }
     }
 
@@ -40,6 +52,14 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            size: self.fallback_allocator.size(),
+            used: self.fallback_allocator.used(),
+            allocated: self.allocated,
+        }
+    }
+
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
         match self.fallback_allocator.allocate_first_fit(layout) {
             Ok(ptr) => ptr.as_ptr(),
@@ -51,7 +71,7 @@ impl FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
+        let ptr = match list_index(&layout) {
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -69,11 +89,16 @@ unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout),
+        };
+        if !ptr.is_null() {
+            allocator.allocated += layout.size();
         }
+        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
+        allocator.allocated -= layout.size();
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
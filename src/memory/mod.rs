@@ -1,9 +1,13 @@
+use allocator::Locked;
+use area_frame_allocator::AreaFrameAllocator;
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use multiboot2::BootInformation;
 use x86_64::addr::PhysAddr;
 use x86_64::registers::control::Cr3;
 use x86_64::structures::paging::{
-    frame::PhysFrame, mapper::PageTableFrameMapping, FrameAllocator, MappedPageTable, Mapper, Page,
-    PageTable, Size1GiB, Size4KiB,
+    frame::PhysFrame, mapper::MapToError, mapper::PageTableFrameMapping, FrameAllocator,
+    MappedPageTable, Mapper, Page, PageTable, Size1GiB, Size2MiB, Size4KiB,
 };
 use x86_64::structures::paging::{OffsetPageTable, PageSize, PageTableFlags as Flags};
 use x86_64::VirtAddr;
@@ -12,6 +16,21 @@ pub mod allocator;
 mod area_frame_allocator;
 mod block_allocator;
 pub mod linked_list;
+
+pub use block_allocator::HeapStats;
+
+/// Virtual address at which the complete physical memory is mapped.
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 1 << 44; // 16 TiB
+/// Start of the virtual region used for uncached device mappings.
+pub const MMIO_START: u64 = 0x_5555_5555_0000;
+pub const MMIO_SIZE: u64 = 1 << 30; // 1 GiB
+
+static MAPPER: OnceCell<Locked<OffsetPageTable<'static>>> = OnceCell::uninit();
+static FRAME_ALLOCATOR: OnceCell<Locked<AreaFrameAllocator>> = OnceCell::uninit();
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+/// Whether page tables are accessed through the offset mapping yet.
+static OFFSET_MAPPED: AtomicBool = AtomicBool::new(false);
+
 struct StartMapping {}
 
 unsafe impl PageTableFrameMapping for StartMapping {
@@ -77,9 +96,110 @@ pub fn init(
 
     let level_4_table = unsafe { active_level_4_table(VirtAddr::new(0)) };
 
-    let offset = VirtAddr::new(1 << 44); // 16 TiB
+    let offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET);
     create_total_offset_mapping(offset, end, &mut allocator, level_4_table);
 
+    OFFSET_MAPPED.store(true, Ordering::Release);
     let mut mapper = unsafe { OffsetPageTable::new(level_4_table, offset) };
     allocator::init_heap(&mut mapper, &mut allocator).expect("heap initialization failed");
+
+    MAPPER.init_once(|| Locked::new(mapper));
+    FRAME_ALLOCATOR.init_once(|| Locked::new(allocator));
+}
+
+/// Usage of physical memory in 4 KiB frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    pub total: usize,
+    pub allocated: usize,
+}
+
+pub fn frame_stats() -> FrameStats {
+    FRAME_ALLOCATOR.get().map_or(
+        FrameStats {
+            total: 0,
+            allocated: 0,
+        },
+        |allocator| {
+            let allocator = allocator.lock();
+            FrameStats {
+                total: allocator.total_frames(),
+                allocated: allocator.allocated_frames(),
+            }
+        },
+    )
+}
+
+/// Returns the address through which the given physical address can be accessed.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET + addr.as_u64())
+}
+
+/// Translates a virtual address by walking the active page tables.
+///
+/// Takes no locks, so it can be used to probe addresses from exception
+/// handlers before dereferencing them.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    let indexes = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table_addr = Cr3::read().0.start_address();
+    for (level, index) in indexes.into_iter().enumerate() {
+        let table_ptr: *const PageTable = if OFFSET_MAPPED.load(Ordering::Acquire) {
+            phys_to_virt(table_addr).as_ptr()
+        } else {
+            // the boot page tables identity map the first GiB
+            table_addr.as_u64() as *const PageTable
+        };
+        let entry = unsafe { &(*table_ptr)[index] };
+        if !entry.flags().contains(Flags::PRESENT) {
+            return None;
+        }
+        let page_size = match level {
+            1 if entry.flags().contains(Flags::HUGE_PAGE) => Size1GiB::SIZE,
+            2 if entry.flags().contains(Flags::HUGE_PAGE) => Size2MiB::SIZE,
+            3 => Size4KiB::SIZE,
+            _ => {
+                table_addr = entry.addr();
+                continue;
+            }
+        };
+        return Some(entry.addr() + (addr.as_u64() & (page_size - 1)));
+    }
+    None
+}
+
+/// Maps `size` bytes of device memory starting at `addr` as uncached.
+///
+/// Every call creates a new mapping in the MMIO region, so drivers should map
+/// their registers once and keep the returned address. Fails with
+/// `FrameAllocationFailed` once the region is exhausted.
+pub fn map_mmio(addr: PhysAddr, size: usize) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let start_frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(addr);
+    let end_frame = PhysFrame::containing_address(addr + (size.max(1) - 1));
+    let frames = PhysFrame::range_inclusive(start_frame, end_frame);
+    let length = (end_frame - start_frame + 1) * Size4KiB::SIZE;
+
+    let start = NEXT_MMIO
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+            next.checked_add(length)
+                .filter(|&end| end <= MMIO_START + MMIO_SIZE)
+        })
+        .map_err(|_| MapToError::FrameAllocationFailed)?;
+
+    let mut mapper = MAPPER.get().expect("memory not initialized").lock();
+    let mut allocator = FRAME_ALLOCATOR
+        .get()
+        .expect("memory not initialized")
+        .lock();
+    let flags = Flags::PRESENT | Flags::WRITABLE | Flags::NO_CACHE | Flags::WRITE_THROUGH;
+    for (i, frame) in frames.enumerate() {
+        let page = Page::containing_address(VirtAddr::new(start) + i as u64 * Size4KiB::SIZE);
+        unsafe { mapper.map_to(page, frame, flags, &mut *allocator)?.flush() };
+    }
+
+    Ok(VirtAddr::new(start) + (addr.as_u64() - start_frame.start_address().as_u64()))
 }
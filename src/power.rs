@@ -0,0 +1,47 @@
+use crate::{cprintln, hlt_loop};
+use core::arch::asm;
+use x86_64::instructions::{interrupts, port::Port, tables::lidt};
+use x86_64::structures::DescriptorTablePointer;
+use x86_64::VirtAddr;
+
+const PS2_STATUS: u16 = 0x64;
+const PS2_COMMAND: u16 = 0x64;
+const INPUT_BUFFER_FULL: u8 = 1 << 1;
+const PULSE_RESET: u8 = 0xfe;
+
+/// Shutdown ports of emulators: QEMU, Bochs and older QEMU, VirtualBox.
+const SHUTDOWN_PORTS: &[(u16, u16)] = &[(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+/// Resets the machine through the keyboard controller and falls back to a
+/// triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    let mut status: Port<u8> = Port::new(PS2_STATUS);
+    let mut command: Port<u8> = Port::new(PS2_COMMAND);
+    unsafe {
+        while status.read() & INPUT_BUFFER_FULL != 0 {}
+        command.write(PULSE_RESET);
+    }
+
+    // any exception without an IDT escalates to a triple fault
+    let idt = DescriptorTablePointer {
+        limit: 0,
+        base: VirtAddr::zero(),
+    };
+    unsafe {
+        lidt(&idt);
+        asm!("int3");
+    }
+    hlt_loop();
+}
+
+/// Powers off emulators. Real hardware needs ACPI, which we can not do yet,
+/// so it is halted instead.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    for &(port, value) in SHUTDOWN_PORTS {
+        unsafe { Port::new(port).write(value) };
+    }
+    cprintln!("shutdown failed, it is now safe to turn off the computer");
+    hlt_loop();
+}
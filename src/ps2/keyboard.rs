@@ -0,0 +1,335 @@
+use super::{layouts::Layout, Ps2Error, Ps2Port};
+use crate::input::{self, InputEvent, Subscription};
+use crate::interrupts::{self, InterruptVectors, IrqReturn};
+use crate::task::deferred;
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam::atomic::AtomicCell;
+use futures::stream::{Stream, StreamExt};
+use pc_keyboard::{
+    DecodeState, DecodedKey, HandleControl, KeyEvent as RawKeyEvent, ScancodeSet as _,
+    ScancodeSet1, ScancodeSet2,
+};
+use spin::Mutex;
+
+pub use pc_keyboard::{KeyCode, KeyState};
+
+// device commands
+const SET_LEDS: u8 = 0xed;
+const SELECT_SCANCODE_SET: u8 = 0xf0;
+const ENABLE_SCANNING: u8 = 0xf4;
+const RESET: u8 = 0xff;
+
+const SELF_TEST_PASSED: u8 = 0xaa;
+const RESET_TIMEOUTS: usize = 10;
+// sent instead of a scancode on errors or buffer overruns
+const KEY_ERROR: u8 = 0x00;
+const OVERRUN: u8 = 0xff;
+
+// LED bits
+const SCROLL_LOCK_LED: u8 = 1 << 0;
+const NUM_LOCK_LED: u8 = 1 << 1;
+const CAPS_LOCK_LED: u8 = 1 << 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScancodeSet {
+    One,
+    Two,
+}
+
+/// State of the modifier and lock keys.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub lshift: bool,
+    pub rshift: bool,
+    pub lctrl: bool,
+    pub rctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    const NONE: Modifiers = Modifiers {
+        lshift: false,
+        rshift: false,
+        lctrl: false,
+        rctrl: false,
+        alt: false,
+        alt_gr: false,
+        caps_lock: false,
+        num_lock: false,
+        scroll_lock: false,
+    };
+
+    pub fn shift(&self) -> bool {
+        self.lshift || self.rshift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.lctrl || self.rctrl
+    }
+
+    fn leds(&self) -> u8 {
+        let mut leds = 0;
+        if self.scroll_lock {
+            leds |= SCROLL_LOCK_LED;
+        }
+        if self.num_lock {
+            leds |= NUM_LOCK_LED;
+        }
+        if self.caps_lock {
+            leds |= CAPS_LOCK_LED;
+        }
+        leds
+    }
+
+    fn to_layout_modifiers(self) -> pc_keyboard::Modifiers {
+        pc_keyboard::Modifiers {
+            lshift: self.lshift,
+            rshift: self.rshift,
+            lctrl: self.lctrl,
+            rctrl: self.rctrl,
+            numlock: self.num_lock,
+            capslock: self.caps_lock,
+            alt_gr: self.alt_gr,
+        }
+    }
+}
+
+/// A key press or release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    /// The modifiers after this event was applied.
+    pub modifiers: Modifiers,
+    /// The character the current layout produces, only set for presses.
+    pub unicode: Option<char>,
+}
+
+static LAYOUT: AtomicCell<Layout> = AtomicCell::new(Layout::Us);
+static SCANCODE_SET: AtomicCell<ScancodeSet> = AtomicCell::new(ScancodeSet::One);
+/// LED state to send once the keyboard acknowledged `SET_LEDS`.
+static PENDING_LEDS: AtomicCell<Option<u8>> = AtomicCell::new(None);
+/// Only used by the interrupt handler.
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+
+/// Resets the keyboard, selects the scancode set and enables its interrupt.
+///
+/// Requires [`super::init`] and must run before interrupts are enabled, as
+/// the responses of the keyboard are polled.
+pub fn init() -> Result<ScancodeSet, Ps2Error> {
+    if !super::ports().first {
+        return Err(Ps2Error::NotPresent);
+    }
+    super::send(Ps2Port::First, RESET)?;
+    // the self test takes a while, so wait for several timeouts
+    let result = (0..RESET_TIMEOUTS).find_map(|_| super::read_data().ok());
+    if result != Some(SELF_TEST_PASSED) {
+        return Err(Ps2Error::SelfTestFailed);
+    }
+    let set = select_scancode_set();
+    SCANCODE_SET.store(set);
+    super::send(Ps2Port::First, SET_LEDS)?;
+    super::send(Ps2Port::First, 0)?;
+    super::send(Ps2Port::First, ENABLE_SCANNING)?;
+
+    super::enable_interrupt(Ps2Port::First)?;
+    interrupts::register_irq(
+        InterruptVectors::Keyboard.irq(),
+        "keyboard",
+        handle_interrupt,
+    )
+    .expect("registering the keyboard failed");
+    Ok(set)
+}
+
+/// Prefers decoding set 2 ourselves and falls back to the translation of
+/// the controller to set 1 if the keyboard rejects the selection.
+fn select_scancode_set() -> ScancodeSet {
+    let set_2 = super::send(Ps2Port::First, SELECT_SCANCODE_SET)
+        .and_then(|_| super::send(Ps2Port::First, 2))
+        .is_ok();
+    if set_2 {
+        ScancodeSet::Two
+    } else {
+        super::set_translation(true).ok();
+        ScancodeSet::One
+    }
+}
+
+pub fn layout() -> Layout {
+    LAYOUT.load()
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout);
+}
+
+/// The layouts that can be selected.
+pub fn layouts() -> &'static [Layout] {
+    Layout::ALL
+}
+
+/// Sets the lock LEDs, the data byte is sent by the interrupt handler once
+/// the keyboard acknowledged the command.
+fn set_leds(leds: u8) {
+    PENDING_LEDS.store(Some(leds));
+    write_without_waiting(SET_LEDS);
+}
+
+/// Sends `value` to the keyboard if the controller takes it right away, and
+/// leaves waiting for the controller to deferred work otherwise. Used by the
+/// interrupt handler, which must not poll the controller.
+fn write_without_waiting(value: u8) {
+    if !super::try_write_data(value) {
+        let write = |value| {
+            super::write_device(Ps2Port::First, value as u8).ok();
+        };
+        deferred::defer_call(write, usize::from(value));
+    }
+}
+
+fn handle_interrupt(_vector: u8) -> IrqReturn {
+    match super::read_data_unchecked() {
+        super::ACK => {
+            if let Some(leds) = PENDING_LEDS.swap(None) {
+                write_without_waiting(leds);
+            }
+        }
+        super::RESEND | KEY_ERROR | OVERRUN => {}
+        scancode => {
+            let event = DECODER.lock().decode(scancode);
+            if let Some(event) = event {
+                input::publish(InputEvent::Key(event));
+            }
+        }
+    }
+    IrqReturn::Handled
+}
+
+/// Turns scancodes into key events and keeps track of the modifiers.
+struct Decoder {
+    state: DecodeState,
+    modifiers: Modifiers,
+}
+
+impl Decoder {
+    const fn new() -> Self {
+        Decoder {
+            state: DecodeState::Start,
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    fn decode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        let event = match SCANCODE_SET.load() {
+            ScancodeSet::One => self.decode_set_1(scancode),
+            ScancodeSet::Two => ScancodeSet2::advance_state(&mut self.state, scancode),
+        };
+        event.ok().flatten().map(|event| self.process(event))
+    }
+
+    /// Set 1 names the ISO keys differently than set 2, see [`Layout`].
+    fn decode_set_1(&mut self, scancode: u8) -> Result<Option<RawKeyEvent>, pc_keyboard::Error> {
+        let iso_key = match scancode & 0x7f {
+            0x2b => Some(KeyCode::HashTilde),
+            0x56 => Some(KeyCode::BackSlash),
+            _ => None,
+        };
+        match iso_key {
+            Some(code) if self.state == DecodeState::Start => {
+                let state = if scancode & 0x80 == 0 {
+                    KeyState::Down
+                } else {
+                    KeyState::Up
+                };
+                Ok(Some(RawKeyEvent::new(code, state)))
+            }
+            _ => ScancodeSet1::advance_state(&mut self.state, scancode),
+        }
+    }
+
+    fn process(&mut self, event: RawKeyEvent) -> KeyEvent {
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+        let leds = modifiers.leds();
+        match event.code {
+            KeyCode::ShiftLeft => modifiers.lshift = down,
+            KeyCode::ShiftRight => modifiers.rshift = down,
+            KeyCode::ControlLeft => modifiers.lctrl = down,
+            KeyCode::ControlRight => modifiers.rctrl = down,
+            KeyCode::AltLeft => modifiers.alt = down,
+            KeyCode::AltRight => modifiers.alt_gr = down,
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumpadLock if down => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            _ => {}
+        }
+        if modifiers.leds() != leds {
+            set_leds(modifiers.leds());
+        }
+
+        let decoded = down.then(|| {
+            layout().map_keycode(
+                event.code,
+                &modifiers.to_layout_modifiers(),
+                HandleControl::Ignore,
+            )
+        });
+        let unicode = match decoded {
+            Some(DecodedKey::Unicode(c)) => Some(c),
+            _ => None,
+        };
+        KeyEvent {
+            code: event.code,
+            state: event.state,
+            modifiers: *modifiers,
+            unicode,
+        }
+    }
+}
+
+/// Stream of the key events from now on, see [`input::subscribe`].
+pub struct KeyEventStream {
+    events: Subscription,
+}
+
+impl KeyEventStream {
+    pub fn new() -> Self {
+        KeyEventStream {
+            events: input::subscribe(input::Filter::KEY),
+        }
+    }
+
+    /// Events lost because this stream was not read fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+}
+
+impl Default for KeyEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        loop {
+            match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(InputEvent::Key(event))) => return Poll::Ready(Some(event)),
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use pc_keyboard::{
+    layouts::{Azerty, Dvorak104Key, Jis109Key, Uk105Key, Us104Key},
+    DecodedKey, HandleControl, KeyCode, KeyboardLayout, Modifiers,
+};
+
+/// Keyboard layouts that can be selected at runtime.
+///
+/// Key codes name physical positions: `HashTilde` is the key next to enter
+/// and `BackSlash` the extra key next to the left shift key of ISO keyboards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    German,
+    French,
+    Dvorak,
+    Japanese,
+}
+
+impl Layout {
+    pub const ALL: &'static [Layout] = &[
+        Layout::Us,
+        Layout::Uk,
+        Layout::German,
+        Layout::French,
+        Layout::Dvorak,
+        Layout::Japanese,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::German => "de",
+            Layout::French => "fr",
+            Layout::Dvorak => "dvorak",
+            Layout::Japanese => "jp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        Layout::ALL
+            .iter()
+            .copied()
+            .find(|layout| layout.name() == name)
+    }
+
+    pub fn map_keycode(
+        self,
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        // ANSI layouts only know the key above enter, as `BackSlash`
+        let ansi_keycode = match keycode {
+            KeyCode::HashTilde => KeyCode::BackSlash,
+            keycode => keycode,
+        };
+        match self {
+            Layout::Us => Us104Key::map_keycode(ansi_keycode, modifiers, handle_ctrl),
+            Layout::Uk => Uk105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::German => De105Key::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::French => Azerty::map_keycode(keycode, modifiers, handle_ctrl),
+            Layout::Dvorak => Dvorak104Key::map_keycode(ansi_keycode, modifiers, handle_ctrl),
+            Layout::Japanese => Jis109Key::map_keycode(keycode, modifiers, handle_ctrl),
+        }
+    }
+}
+
+/// A German 105-key (QWERTZ) keyboard.
+pub struct De105Key;
+
+impl KeyboardLayout for De105Key {
+    fn map_keycode(
+        keycode: KeyCode,
+        modifiers: &Modifiers,
+        handle_ctrl: HandleControl,
+    ) -> DecodedKey {
+        let shifted = modifiers.is_shifted();
+        // (normal, shifted, AltGr)
+        let symbols = match keycode {
+            KeyCode::BackTick => ('^', '°', None),
+            KeyCode::Key1 => ('1', '!', None),
+            KeyCode::Key2 => ('2', '"', Some('²')),
+            KeyCode::Key3 => ('3', '§', Some('³')),
+            KeyCode::Key4 => ('4', '$', None),
+            KeyCode::Key5 => ('5', '%', None),
+            KeyCode::Key6 => ('6', '&', None),
+            KeyCode::Key7 => ('7', '/', Some('{')),
+            KeyCode::Key8 => ('8', '(', Some('[')),
+            KeyCode::Key9 => ('9', ')', Some(']')),
+            KeyCode::Key0 => ('0', '=', Some('}')),
+            KeyCode::Minus => ('ß', '?', Some('\\')),
+            KeyCode::Equals => ('´', '`', None),
+            KeyCode::BracketSquareRight => ('+', '*', Some('~')),
+            KeyCode::HashTilde => ('#', '\'', None),
+            KeyCode::BackSlash => ('<', '>', Some('|')),
+            KeyCode::Comma => (',', ';', None),
+            KeyCode::Fullstop => ('.', ':', None),
+            KeyCode::Slash => ('-', '_', None),
+            _ => return map_letter(keycode, modifiers, handle_ctrl),
+        };
+        match symbols {
+            (_, _, Some(alt_gr)) if modifiers.alt_gr => DecodedKey::Unicode(alt_gr),
+            (_, shifted_symbol, _) if shifted => DecodedKey::Unicode(shifted_symbol),
+            (symbol, _, _) => DecodedKey::Unicode(symbol),
+        }
+    }
+}
+
+/// Maps the letters of the German layout, which differ from the US layout in
+/// the umlauts, the swapped Y and Z and a few AltGr symbols.
+fn map_letter(keycode: KeyCode, modifiers: &Modifiers, handle_ctrl: HandleControl) -> DecodedKey {
+    let (letter, alt_gr) = match keycode {
+        KeyCode::BracketSquareLeft => ('ü', None),
+        KeyCode::SemiColon => ('ö', None),
+        KeyCode::Quote => ('ä', None),
+        KeyCode::Q => ('q', Some('@')),
+        KeyCode::E => ('e', Some('€')),
+        KeyCode::M => ('m', Some('µ')),
+        KeyCode::Y => return Us104Key::map_keycode(KeyCode::Z, modifiers, handle_ctrl),
+        KeyCode::Z => return Us104Key::map_keycode(KeyCode::Y, modifiers, handle_ctrl),
+        _ => return Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+    };
+    match alt_gr {
+        Some(alt_gr) if modifiers.alt_gr => DecodedKey::Unicode(alt_gr),
+        _ if letter.is_ascii() => Us104Key::map_keycode(keycode, modifiers, handle_ctrl),
+        _ if modifiers.is_caps() => {
+            DecodedKey::Unicode(letter.to_uppercase().next().unwrap_or(letter))
+        }
+        _ => DecodedKey::Unicode(letter),
+    }
+}
@@ -0,0 +1,241 @@
+pub mod keyboard;
+pub mod layouts;
+pub mod mouse;
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::port::Port;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+// status register bits
+const OUTPUT_FULL: u8 = 1 << 0;
+const INPUT_FULL: u8 = 1 << 1;
+
+// controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_PORT_2: u8 = 0xa7;
+const ENABLE_PORT_2: u8 = 0xa8;
+const TEST_PORT_2: u8 = 0xa9;
+const SELF_TEST: u8 = 0xaa;
+const TEST_PORT_1: u8 = 0xab;
+const DISABLE_PORT_1: u8 = 0xad;
+const ENABLE_PORT_1: u8 = 0xae;
+const WRITE_PORT_2: u8 = 0xd4;
+
+// configuration byte bits
+const PORT_1_INTERRUPT: u8 = 1 << 0;
+const PORT_2_INTERRUPT: u8 = 1 << 1;
+const PORT_2_CLOCK_DISABLED: u8 = 1 << 5;
+const TRANSLATION: u8 = 1 << 6;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+// device responses
+pub const ACK: u8 = 0xfa;
+pub const RESEND: u8 = 0xfe;
+
+/// Polls of the status register before giving up on the controller.
+const TIMEOUT: usize = 100_000;
+const RETRIES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    SelfTestFailed,
+    PortTestFailed,
+    /// The device did not acknowledge a command.
+    NoAck,
+    NotPresent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    /// The keyboard port.
+    First,
+    /// The auxiliary port, usually connected to a mouse.
+    Second,
+}
+
+/// The ports that passed the interface test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ports {
+    pub first: bool,
+    pub second: bool,
+}
+
+static PORTS: OnceCell<Ports> = OnceCell::uninit();
+
+fn status() -> u8 {
+    unsafe { Port::new(STATUS).read() }
+}
+
+fn wait_input_empty() -> Result<(), Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if status() & INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Polls for a byte from the controller or a device.
+///
+/// Only use this while the interrupts of the port are masked, otherwise the
+/// interrupt handler takes the byte.
+pub fn read_data() -> Result<u8, Ps2Error> {
+    for _ in 0..TIMEOUT {
+        if status() & OUTPUT_FULL != 0 {
+            return Ok(read_data_unchecked());
+        }
+        core::hint::spin_loop();
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Reads the data port without waiting, as done by the interrupt handlers.
+pub fn read_data_unchecked() -> u8 {
+    unsafe { Port::new(DATA).read() }
+}
+
+pub fn write_data(value: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { Port::new(DATA).write(value) };
+    Ok(())
+}
+
+/// Writes to the first port only if the controller can take the byte at
+/// once, for interrupt handlers. Returns `false` if it is busy.
+pub fn try_write_data(value: u8) -> bool {
+    if status() & INPUT_FULL != 0 {
+        return false;
+    }
+    unsafe { Port::new(DATA).write(value) };
+    true
+}
+
+fn write_command(command: u8) -> Result<(), Ps2Error> {
+    wait_input_empty()?;
+    unsafe { Port::new(COMMAND).write(command) };
+    Ok(())
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    write_command(READ_CONFIG)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    write_command(WRITE_CONFIG)?;
+    write_data(config)
+}
+
+fn flush_output() {
+    while status() & OUTPUT_FULL != 0 {
+        read_data_unchecked();
+    }
+}
+
+/// Writes a byte to the device on `port` without waiting for a response.
+pub fn write_device(port: Ps2Port, value: u8) -> Result<(), Ps2Error> {
+    if port == Ps2Port::Second {
+        write_command(WRITE_PORT_2)?;
+    }
+    write_data(value)
+}
+
+/// Sends a command byte to a device and waits for the acknowledgement,
+/// resending it if the device asks for that.
+pub fn send(port: Ps2Port, value: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        write_device(port, value)?;
+        match read_data()? {
+            ACK => return Ok(()),
+            RESEND => continue,
+            _ => return Err(Ps2Error::NoAck),
+        }
+    }
+    Err(Ps2Error::NoAck)
+}
+
+/// Resets and tests the 8042 controller and enables the working ports.
+///
+/// Device interrupts stay disabled in the controller until a driver calls
+/// [`enable_interrupt`].
+pub fn init() -> Result<Ports, Ps2Error> {
+    write_command(DISABLE_PORT_1)?;
+    write_command(DISABLE_PORT_2)?;
+    flush_output();
+
+    let config = read_config()? & !(PORT_1_INTERRUPT | PORT_2_INTERRUPT | TRANSLATION);
+    write_config(config)?;
+
+    write_command(SELF_TEST)?;
+    if read_data()? != SELF_TEST_PASSED {
+        return Err(Ps2Error::SelfTestFailed);
+    }
+    // the self test may reset the controller
+    write_config(config)?;
+
+    // the second clock is only enabled by this command on dual port controllers
+    let mut dual_port = false;
+    if config & PORT_2_CLOCK_DISABLED != 0 {
+        write_command(ENABLE_PORT_2)?;
+        dual_port = read_config()? & PORT_2_CLOCK_DISABLED == 0;
+        write_command(DISABLE_PORT_2)?;
+    }
+
+    write_command(TEST_PORT_1)?;
+    let first = read_data()? == PORT_TEST_PASSED;
+    let second = dual_port && {
+        write_command(TEST_PORT_2)?;
+        read_data()? == PORT_TEST_PASSED
+    };
+    if !first && !second {
+        return Err(Ps2Error::PortTestFailed);
+    }
+
+    if first {
+        write_command(ENABLE_PORT_1)?;
+    }
+    if second {
+        write_command(ENABLE_PORT_2)?;
+    }
+    flush_output();
+
+    let ports = Ports { first, second };
+    PORTS.init_once(|| ports);
+    Ok(ports)
+}
+
+/// The ports found by [`init`].
+pub fn ports() -> Ports {
+    PORTS.get().copied().unwrap_or(Ports {
+        first: false,
+        second: false,
+    })
+}
+
+/// Lets the controller raise IRQ 1 or IRQ 12 for `port`.
+pub fn enable_interrupt(port: Ps2Port) -> Result<(), Ps2Error> {
+    let bit = match port {
+        Ps2Port::First => PORT_1_INTERRUPT,
+        Ps2Port::Second => PORT_2_INTERRUPT,
+    };
+    write_config(read_config()? | bit)
+}
+
+/// Makes the controller translate scancode set 2 to set 1 for the first port.
+pub fn set_translation(enabled: bool) -> Result<(), Ps2Error> {
+    let config = read_config()?;
+    let config = if enabled {
+        config | TRANSLATION
+    } else {
+        config & !TRANSLATION
+    };
+    write_config(config)
+}
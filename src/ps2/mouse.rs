@@ -0,0 +1,236 @@
+use super::{Ps2Error, Ps2Port};
+use crate::input::{self, InputEvent, Subscription};
+use crate::interrupts::{self, InterruptVectors, IrqReturn};
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam::atomic::AtomicCell;
+use futures::stream::{Stream, StreamExt};
+use spin::Mutex;
+
+// device commands
+const SET_SAMPLE_RATE: u8 = 0xf3;
+const GET_DEVICE_ID: u8 = 0xf2;
+const ENABLE_REPORTING: u8 = 0xf4;
+const SET_DEFAULTS: u8 = 0xf6;
+const RESET: u8 = 0xff;
+
+const SELF_TEST_PASSED: u8 = 0xaa;
+const RESET_TIMEOUTS: usize = 10;
+
+// first packet byte
+const LEFT_BUTTON: u8 = 1 << 0;
+const RIGHT_BUTTON: u8 = 1 << 1;
+const MIDDLE_BUTTON: u8 = 1 << 2;
+const ALWAYS_ONE: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+// fourth packet byte of five button mice
+const FOURTH_BUTTON: u8 = 1 << 4;
+const FIFTH_BUTTON: u8 = 1 << 5;
+
+/// The protocol the mouse agreed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseKind {
+    /// Three buttons and 3-byte packets.
+    Standard,
+    /// IntelliMouse with a scroll wheel and 4-byte packets.
+    Wheel,
+    /// IntelliMouse Explorer with a scroll wheel and five buttons.
+    FiveButton,
+}
+
+impl MouseKind {
+    fn packet_size(self) -> usize {
+        match self {
+            MouseKind::Standard => 3,
+            MouseKind::Wheel | MouseKind::FiveButton => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+    pub fourth: bool,
+    pub fifth: bool,
+}
+
+/// Movement since the last event, in device units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub dx: i16,
+    /// Positive values move down, like screen coordinates.
+    pub dy: i16,
+    /// Positive values scroll down.
+    pub wheel: i8,
+    pub buttons: MouseButtons,
+}
+
+struct Packet {
+    bytes: [u8; 4],
+    length: usize,
+}
+
+static KIND: AtomicCell<MouseKind> = AtomicCell::new(MouseKind::Standard);
+/// Only used by the interrupt handler.
+static PACKET: Mutex<Packet> = Mutex::new(Packet {
+    bytes: [0; 4],
+    length: 0,
+});
+
+/// Resets the mouse, enables the scroll wheel if it has one and starts
+/// reporting.
+///
+/// Requires [`super::init`] and must run before interrupts are enabled, as
+/// the responses of the mouse are polled.
+pub fn init() -> Result<MouseKind, Ps2Error> {
+    if !super::ports().second {
+        return Err(Ps2Error::NotPresent);
+    }
+    super::send(Ps2Port::Second, RESET)?;
+    let result = (0..RESET_TIMEOUTS).find_map(|_| super::read_data().ok());
+    if result != Some(SELF_TEST_PASSED) {
+        return Err(Ps2Error::SelfTestFailed);
+    }
+    // the device id follows the self test result
+    super::read_data()?;
+    super::send(Ps2Port::Second, SET_DEFAULTS)?;
+
+    let kind = detect_kind()?;
+    KIND.store(kind);
+    super::send(Ps2Port::Second, ENABLE_REPORTING)?;
+
+    super::enable_interrupt(Ps2Port::Second)?;
+    interrupts::register_irq(InterruptVectors::Mouse.irq(), "mouse", handle_interrupt)
+        .expect("registering the mouse failed");
+    Ok(kind)
+}
+
+/// IntelliMice unlock their extensions when they see a magic sequence of
+/// sample rates, and change their device id.
+fn detect_kind() -> Result<MouseKind, Ps2Error> {
+    let mut kind = MouseKind::Standard;
+    if unlock(&[200, 100, 80])? == 3 {
+        kind = MouseKind::Wheel;
+        if unlock(&[200, 200, 80])? == 4 {
+            kind = MouseKind::FiveButton;
+        }
+    }
+    // restore the default rate
+    set_sample_rate(100)?;
+    Ok(kind)
+}
+
+fn unlock(rates: &[u8]) -> Result<u8, Ps2Error> {
+    for &rate in rates {
+        set_sample_rate(rate)?;
+    }
+    super::send(Ps2Port::Second, GET_DEVICE_ID)?;
+    super::read_data()
+}
+
+fn set_sample_rate(rate: u8) -> Result<(), Ps2Error> {
+    super::send(Ps2Port::Second, SET_SAMPLE_RATE)?;
+    super::send(Ps2Port::Second, rate)
+}
+
+pub fn kind() -> MouseKind {
+    KIND.load()
+}
+
+fn handle_interrupt(_vector: u8) -> IrqReturn {
+    let byte = super::read_data_unchecked();
+    let mut packet = PACKET.lock();
+    // resynchronize on the bit that is set in every first byte
+    if packet.length == 0 && byte & ALWAYS_ONE == 0 {
+        return IrqReturn::Handled;
+    }
+    let length = packet.length;
+    packet.bytes[length] = byte;
+    packet.length += 1;
+    let kind = kind();
+    if packet.length == kind.packet_size() {
+        packet.length = 0;
+        if let Some(event) = decode(&packet.bytes, kind) {
+            input::publish(InputEvent::Mouse(event));
+        }
+    }
+    IrqReturn::Handled
+}
+
+fn decode(bytes: &[u8; 4], kind: MouseKind) -> Option<MouseEvent> {
+    let flags = bytes[0];
+    if flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+        return None;
+    }
+    // the sign bits extend the movement bytes to 9 bit
+    let extend = |value: u8, negative: bool| i16::from(value) - if negative { 256 } else { 0 };
+    let mut buttons = MouseButtons {
+        left: flags & LEFT_BUTTON != 0,
+        right: flags & RIGHT_BUTTON != 0,
+        middle: flags & MIDDLE_BUTTON != 0,
+        ..MouseButtons::default()
+    };
+    let wheel = match kind {
+        MouseKind::Standard => 0,
+        MouseKind::Wheel => bytes[3] as i8,
+        MouseKind::FiveButton => {
+            buttons.fourth = bytes[3] & FOURTH_BUTTON != 0;
+            buttons.fifth = bytes[3] & FIFTH_BUTTON != 0;
+            // 4 bit two's complement
+            ((bytes[3] << 4) as i8) >> 4
+        }
+    };
+    Some(MouseEvent {
+        dx: extend(bytes[1], flags & X_SIGN != 0),
+        dy: -extend(bytes[2], flags & Y_SIGN != 0),
+        wheel,
+        buttons,
+    })
+}
+
+/// Stream of the mouse events from now on, see [`input::subscribe`].
+pub struct MouseEventStream {
+    events: Subscription,
+}
+
+impl MouseEventStream {
+    pub fn new() -> Self {
+        MouseEventStream {
+            events: input::subscribe(input::Filter::MOUSE),
+        }
+    }
+
+    /// Events lost because this stream was not read fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+}
+
+impl Default for MouseEventStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for MouseEventStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        loop {
+            match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(InputEvent::Mouse(event))) => return Poll::Ready(Some(event)),
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
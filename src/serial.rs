@@ -1,16 +1,46 @@
-use core::fmt::Write;
+use crate::input::{self, InputEvent, Subscription};
+use crate::interrupts::{self, InterruptVectors, IrqReturn};
+use crate::sync::IrqSpinlock;
+use conquer_once::spin::OnceCell;
+use core::{
+    fmt::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use crossbeam::queue::ArrayQueue;
+use futures::stream::{Stream, StreamExt};
 use lazy_static::lazy_static;
-use spin::Mutex;
-use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+
+const BUFFER_SIZE: usize = 4096;
+/// Bytes the transmitter FIFO takes at once after signalling it is empty.
+const TX_FIFO_SIZE: usize = 16;
+
+// register offsets
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+const INTERRUPT_ID: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+
+// interrupt enable bits
+const RX_AVAILABLE: u8 = 1 << 0;
+const TX_EMPTY: u8 = 1 << 1;
+
+// line status bits
+const DATA_READY: u8 = 1 << 0;
+const THR_EMPTY: u8 = 1 << 5;
+
+const NO_INTERRUPT_PENDING: u8 = 1 << 0;
 
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments) {
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        SERIAL1
-            .lock()
-            .write_fmt(args)
-            .expect("Printing to serial failed");
-    });
+    Writer(&COM1)
+        .write_fmt(args)
+        .expect("Printing to serial failed");
 }
 
 /// Prints to the host through the serial interface.
@@ -28,10 +58,288 @@ macro_rules! sprintln {
     ($($arg:tt)*) => ($crate::sprint!("{}\n", format_args!($($arg)*)));
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+}
+
+impl ComPort {
+    fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3f8,
+            ComPort::Com2 => 0x2f8,
+        }
+    }
+
+    fn vector(self) -> InterruptVectors {
+        match self {
+            ComPort::Com1 => InterruptVectors::Com1,
+            ComPort::Com2 => InterruptVectors::Com2,
+        }
+    }
+}
+
+/// Register access of a 16550 UART.
+struct Uart {
+    base: u16,
+}
+
+impl Uart {
+    fn read(&self, register: u16) -> u8 {
+        unsafe { Port::new(self.base + register).read() }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        unsafe { Port::new(self.base + register).write(value) }
+    }
+
+    /// Configures 38400 baud 8N1 with FIFOs and all interrupts disabled.
+    fn init(&mut self) {
+        self.write(INTERRUPT_ENABLE, 0);
+        // set the divisor latch to 3
+        self.write(LINE_CONTROL, 0x80);
+        self.write(DATA, 0x03);
+        self.write(INTERRUPT_ENABLE, 0);
+        self.write(LINE_CONTROL, 0x03);
+        // enable and clear the FIFOs, RX interrupt after 14 bytes
+        self.write(FIFO_CONTROL, 0xc7);
+        // DTR, RTS and OUT2, which connects the interrupt line
+        self.write(MODEM_CONTROL, 0x0b);
+    }
+
+    fn can_send(&self) -> bool {
+        self.read(LINE_STATUS) & THR_EMPTY != 0
+    }
+
+    fn send_blocking(&mut self, byte: u8) {
+        while !self.can_send() {
+            core::hint::spin_loop();
+        }
+        self.write(DATA, byte);
+    }
+
+    fn try_receive(&mut self) -> Option<u8> {
+        (self.read(LINE_STATUS) & DATA_READY != 0).then(|| self.read(DATA))
+    }
+
+    fn set_interrupt(&mut self, interrupt: u8, enabled: bool) {
+        let enable = self.read(INTERRUPT_ENABLE);
+        let enable = if enabled {
+            enable | interrupt
+        } else {
+            enable & !interrupt
+        };
+        self.write(INTERRUPT_ENABLE, enable);
+    }
+}
+
+/// Transmit buffer used once the port is interrupt driven, received bytes
+/// are published as input events.
+struct Buffers {
+    tx: ArrayQueue<u8>,
+}
+
+/// A serial port that polls until [`Serial::enable_interrupts`] is called and
+/// uses interrupts and ring buffers afterwards.
+pub struct Serial {
+    port: ComPort,
+    uart: IrqSpinlock<Uart>,
+    buffers: OnceCell<Buffers>,
+}
+
 lazy_static! {
-    pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
-        serial_port.init();
-        Mutex::new(serial_port)
-    };
+    pub static ref COM1: Serial = Serial::new(ComPort::Com1);
+    pub static ref COM2: Serial = Serial::new(ComPort::Com2);
+}
+
+/// Switches both ports to interrupt mode, requires the heap.
+pub fn init() {
+    COM1.enable_interrupts();
+    COM2.enable_interrupts();
+}
+
+impl Serial {
+    fn new(port: ComPort) -> Serial {
+        let mut uart = Uart { base: port.base() };
+        uart.init();
+        Serial {
+            port,
+            uart: IrqSpinlock::new(uart),
+            buffers: OnceCell::uninit(),
+        }
+    }
+
+    pub fn enable_interrupts(&self) {
+        self.buffers.init_once(|| Buffers {
+            tx: ArrayQueue::new(BUFFER_SIZE),
+        });
+        interrupts::register_irq(self.port.vector().irq(), "serial", handle_interrupt)
+            .expect("registering the serial port failed");
+        self.uart.lock().set_interrupt(RX_AVAILABLE, true);
+    }
+
+    /// Queues `bytes` for transmission.
+    ///
+    /// With interrupts disabled, e.g. while panicking, the bytes are written
+    /// synchronously after everything queued before them.
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        let interrupts_enabled = x86_64::instructions::interrupts::are_enabled();
+        let mut uart = self.uart.lock();
+        self.write_locked(&mut uart, bytes, interrupts_enabled);
+    }
+
+    /// Like [`Serial::write_bytes`], but gives up and returns `false` if the
+    /// port is in use, e.g. by the code an exception interrupted.
+    pub fn try_write_bytes(&self, bytes: &[u8]) -> bool {
+        let interrupts_enabled = x86_64::instructions::interrupts::are_enabled();
+        match self.uart.try_lock() {
+            Some(mut uart) => {
+                self.write_locked(&mut uart, bytes, interrupts_enabled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn write_locked(&self, uart: &mut Uart, bytes: &[u8], interrupts_enabled: bool) {
+        match self.buffers.get() {
+            Some(buffers) if interrupts_enabled => {
+                for &byte in bytes {
+                    // make room by sending the oldest byte ourselves
+                    while buffers.tx.push(byte).is_err() {
+                        if let Some(oldest) = buffers.tx.pop() {
+                            uart.send_blocking(oldest);
+                        }
+                    }
+                }
+                start_transmission(uart, buffers);
+            }
+            buffers => {
+                while let Some(byte) = buffers.and_then(|buffers| buffers.tx.pop()) {
+                    uart.send_blocking(byte);
+                }
+                for &byte in bytes {
+                    uart.send_blocking(byte);
+                }
+            }
+        }
+    }
+
+    /// Stream of the bytes received from now on, see [`input::subscribe`].
+    pub fn stream(&self) -> SerialStream {
+        SerialStream {
+            port: self.port,
+            events: input::subscribe(input::Filter::SERIAL),
+        }
+    }
+
+    fn handle_interrupt(&self) -> IrqReturn {
+        let buffers = match self.buffers.get() {
+            Some(buffers) => buffers,
+            None => return IrqReturn::NotHandled,
+        };
+        let mut uart = self.uart.lock();
+        let mut handled = IrqReturn::NotHandled;
+        loop {
+            let id = uart.read(INTERRUPT_ID);
+            if id & NO_INTERRUPT_PENDING != 0 {
+                break;
+            }
+            handled = IrqReturn::Handled;
+            match (id >> 1) & 0x7 {
+                // received data or character timeout
+                0b010 | 0b110 => {
+                    while let Some(byte) = uart.try_receive() {
+                        input::publish(InputEvent::Serial(self.port, byte));
+                    }
+                }
+                0b001 => start_transmission(&mut uart, buffers),
+                0b011 => {
+                    uart.read(LINE_STATUS);
+                }
+                _ => {
+                    uart.read(MODEM_STATUS);
+                }
+            }
+        }
+        handled
+    }
+}
+
+/// Fills the transmitter and enables the empty interrupt while bytes remain.
+fn start_transmission(uart: &mut Uart, buffers: &Buffers) {
+    if uart.can_send() {
+        for _ in 0..TX_FIFO_SIZE {
+            match buffers.tx.pop() {
+                Some(byte) => uart.write(DATA, byte),
+                None => break,
+            }
+        }
+    }
+    uart.set_interrupt(TX_EMPTY, !buffers.tx.is_empty());
+}
+
+fn handle_interrupt(vector: u8) -> IrqReturn {
+    if vector == InterruptVectors::Com1.as_u8() {
+        COM1.handle_interrupt()
+    } else {
+        COM2.handle_interrupt()
+    }
+}
+
+/// `fmt::Write` adapter for a serial port.
+pub struct Writer<'a>(pub &'a Serial);
+
+impl Write for Writer<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes straight to the UART without locking or buffering, for crash
+/// reports. Bytes still queued for transmission are lost.
+pub struct EmergencyWriter(pub ComPort);
+
+impl Write for EmergencyWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut uart = Uart {
+            base: self.0.base(),
+        };
+        for byte in s.bytes() {
+            uart.send_blocking(byte);
+        }
+        Ok(())
+    }
+}
+
+pub struct SerialStream {
+    port: ComPort,
+    events: Subscription,
+}
+
+impl SerialStream {
+    /// Bytes lost because this stream was not read fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.events.dropped()
+    }
+}
+
+impl Stream for SerialStream {
+    type Item = u8;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u8>> {
+        let port = self.port;
+        loop {
+            match self.events.poll_next_unpin(cx) {
+                Poll::Ready(Some(InputEvent::Serial(from, byte))) if from == port => {
+                    return Poll::Ready(Some(byte))
+                }
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
 }
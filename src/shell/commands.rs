@@ -0,0 +1,238 @@
+use super::Console;
+use crate::ps2::{keyboard, layouts::Layout};
+use crate::{cprint, cprintln, dmesg, input, interrupts, logging, memory, power, task, time};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::str::FromStr;
+use lazy_static::lazy_static;
+use log::LevelFilter;
+use spin::Mutex;
+
+/// A command gets the words following its name.
+pub type CommandFn = fn(&[&str]);
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+lazy_static! {
+    static ref COMMANDS: Mutex<BTreeMap<&'static str, Command>> = {
+        let mut commands = BTreeMap::new();
+        for command in BUILTINS {
+            commands.insert(command.name, *command);
+        }
+        Mutex::new(commands)
+    };
+}
+
+const BUILTINS: &[Command] = &[
+    Command {
+        name: "help",
+        help: "list all commands",
+        run: help,
+    },
+    Command {
+        name: "mem",
+        help: "show heap and physical memory usage",
+        run: mem,
+    },
+    Command {
+        name: "tasks",
+        help: "list the tasks of the executor",
+        run: tasks,
+    },
+    Command {
+        name: "uptime",
+        help: "show the time since boot",
+        run: uptime,
+    },
+    Command {
+        name: "irq",
+        help: "show interrupt statistics",
+        run: irq,
+    },
+    Command {
+        name: "input",
+        help: "show input event statistics",
+        run: input_stats,
+    },
+    Command {
+        name: "dmesg",
+        help: "show the kernel log, -c clears it",
+        run: dmesg,
+    },
+    Command {
+        name: "loglevel",
+        help: "show or set the log level: [module] level",
+        run: loglevel,
+    },
+    Command {
+        name: "layout",
+        help: "show or select the keyboard layout",
+        run: layout,
+    },
+    Command {
+        name: "reboot",
+        help: "restart the machine",
+        run: |_| power::reboot(),
+    },
+    Command {
+        name: "shutdown",
+        help: "power off the machine",
+        run: |_| power::shutdown(),
+    },
+    Command {
+        name: "clear",
+        help: "clear the screen",
+        run: clear,
+    },
+];
+
+/// Adds a command to the shell. Returns `false` if the name is taken.
+pub fn register(name: &'static str, help: &'static str, run: CommandFn) -> bool {
+    let mut commands = COMMANDS.lock();
+    if commands.contains_key(name) {
+        return false;
+    }
+    commands.insert(name, Command { name, help, run });
+    true
+}
+
+pub fn unregister(name: &str) -> bool {
+    COMMANDS.lock().remove(name).is_some()
+}
+
+/// Runs a command line.
+pub fn run(line: &str) {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, arguments) = match words.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    // don't hold the lock, so commands can register other commands
+    let command = COMMANDS.lock().get(name).copied();
+    match command {
+        Some(command) => (command.run)(arguments),
+        None => {
+            cprintln!("unknown command `{name}`, try `help`");
+        }
+    }
+}
+
+fn help(_arguments: &[&str]) {
+    let commands: Vec<Command> = COMMANDS.lock().values().copied().collect();
+    for command in commands {
+        cprintln!("  {:10} {}", command.name, command.help);
+    }
+}
+
+fn mem(_arguments: &[&str]) {
+    let heap = memory::allocator::heap_stats();
+    let frames = memory::frame_stats();
+    cprintln!(
+        "heap:   {} of {} KiB in use, {} KiB allocated",
+        heap.used / 1024,
+        heap.size / 1024,
+        heap.allocated / 1024
+    );
+    cprintln!(
+        "frames: {} of {} in use ({} of {} MiB)",
+        frames.allocated,
+        frames.total,
+        frames.allocated / 256,
+        frames.total / 256
+    );
+}
+
+fn tasks(_arguments: &[&str]) {
+    cprintln!("    id      polls priority    name");
+    for task in task::executor::tasks() {
+        cprintln!(
+            "{:6} {:10} {:<11} {}",
+            task.id,
+            task.polls,
+            task.priority.name(),
+            task.name.unwrap_or("-")
+        );
+    }
+}
+
+fn uptime(_arguments: &[&str]) {
+    let uptime = time::uptime().as_secs();
+    cprintln!(
+        "up {}:{:02}:{:02}, time is {}",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60,
+        time::now()
+    );
+}
+
+fn irq(_arguments: &[&str]) {
+    interrupts::stats::write_table(&mut Console).unwrap();
+}
+
+fn input_stats(_arguments: &[&str]) {
+    cprintln!(
+        "{} events published, {} dropped, {} subscribers",
+        input::published(),
+        input::dropped(),
+        input::subscribers()
+    );
+}
+
+fn dmesg(arguments: &[&str]) {
+    dmesg::read(&mut Console).unwrap();
+    if arguments.first() == Some(&"-c") {
+        dmesg::clear();
+    }
+}
+
+fn loglevel(arguments: &[&str]) {
+    let (module, level) = match arguments {
+        [] => {
+            cprintln!("default: {}", logging::default_level());
+            for (module, level) in logging::module_levels() {
+                cprintln!("{module}: {level}");
+            }
+            return;
+        }
+        [level] => (None, level),
+        [module, level, ..] => (Some(module), level),
+    };
+    match (LevelFilter::from_str(level), module) {
+        (Ok(level), Some(module)) => logging::set_level(module, level),
+        (Ok(level), None) => logging::set_default_level(level),
+        (Err(_), _) => {
+            cprintln!("unknown level `{level}`, use off, error, warn, info, debug or trace");
+        }
+    }
+}
+
+fn layout(arguments: &[&str]) {
+    match arguments.first() {
+        Some(name) => match Layout::from_name(name) {
+            Some(layout) => keyboard::set_layout(layout),
+            None => {
+                cprintln!("unknown layout `{name}`");
+            }
+        },
+        None => {
+            let names: Vec<&str> = keyboard::layouts()
+                .iter()
+                .map(|layout| layout.name())
+                .collect();
+            cprintln!(
+                "layout: {}, available: {}",
+                keyboard::layout().name(),
+                names.join(" ")
+            );
+        }
+    }
+}
+
+fn clear(_arguments: &[&str]) {
+    cprint!("\x1b[2J\x1b[H");
+}
@@ -0,0 +1,268 @@
+pub mod commands;
+
+use crate::input::{self, InputEvent};
+use crate::ps2::keyboard::{KeyCode, KeyEvent, KeyState};
+use crate::serial::ComPort;
+use crate::{cprint, vga};
+use alloc::{collections::VecDeque, string::String};
+use core::fmt;
+use futures::stream::StreamExt;
+
+const PROMPT: &str = "> ";
+const HISTORY_SIZE: usize = 32;
+const BACKSPACE: char = '\x08';
+const DELETE: char = '\x7f';
+
+/// `fmt::Write` adapter printing to the screen and the serial port.
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        cprint!("{s}");
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Backspace,
+    Delete,
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+fn decode_keyboard(event: KeyEvent) -> Option<Key> {
+    if event.state != KeyState::Down {
+        return None;
+    }
+    match (event.unicode, event.code) {
+        (Some('\n'), _) => Some(Key::Enter),
+        (Some(BACKSPACE), _) => Some(Key::Backspace),
+        (Some(DELETE), _) => Some(Key::Delete),
+        (Some(c), _) => Some(Key::Char(c)),
+        (None, KeyCode::ArrowUp) => Some(Key::Up),
+        (None, KeyCode::ArrowDown) => Some(Key::Down),
+        (None, KeyCode::ArrowLeft) => Some(Key::Left),
+        (None, KeyCode::ArrowRight) => Some(Key::Right),
+        (None, KeyCode::Home) => Some(Key::Home),
+        (None, KeyCode::End) => Some(Key::End),
+        (None, _) => None,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    None,
+    Escape,
+    /// Inside a control sequence, with the numeric parameter read so far.
+    Csi(u8),
+}
+
+/// Turns the bytes a VT100 terminal sends into keys.
+struct SerialDecoder {
+    state: EscapeState,
+    last_was_cr: bool,
+}
+
+impl SerialDecoder {
+    fn new() -> Self {
+        SerialDecoder {
+            state: EscapeState::None,
+            last_was_cr: false,
+        }
+    }
+
+    fn decode(&mut self, byte: u8) -> Option<Key> {
+        let last_was_cr = core::mem::replace(&mut self.last_was_cr, byte == b'\r');
+        match self.state {
+            EscapeState::None => match byte {
+                0x1b => {
+                    self.state = EscapeState::Escape;
+                    None
+                }
+                // terminals send CR, CRLF or LF for enter
+                b'\n' if last_was_cr => None,
+                b'\r' | b'\n' => Some(Key::Enter),
+                0x08 | 0x7f => Some(Key::Backspace),
+                byte => Some(Key::Char(char::from(byte))),
+            },
+            EscapeState::Escape => {
+                self.state = match byte {
+                    b'[' => EscapeState::Csi(0),
+                    _ => EscapeState::None,
+                };
+                None
+            }
+            EscapeState::Csi(parameter) => {
+                if byte.is_ascii_digit() {
+                    self.state = EscapeState::Csi(parameter.saturating_mul(10) + (byte - b'0'));
+                    return None;
+                }
+                self.state = EscapeState::None;
+                match (byte, parameter) {
+                    (b'A', _) => Some(Key::Up),
+                    (b'B', _) => Some(Key::Down),
+                    (b'C', _) => Some(Key::Right),
+                    (b'D', _) => Some(Key::Left),
+                    (b'H', _) | (b'~', 1 | 7) => Some(Key::Home),
+                    (b'F', _) | (b'~', 4 | 8) => Some(Key::End),
+                    (b'~', 3) => Some(Key::Delete),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Line editing with history, echoing to the console.
+struct LineEditor {
+    /// Only printable ASCII, so byte and character positions match.
+    line: String,
+    cursor: usize,
+    history: VecDeque<String>,
+    /// Position in `history` while browsing it, the edited line is kept in `draft`.
+    history_index: Option<usize>,
+    draft: String,
+}
+
+impl LineEditor {
+    fn new() -> Self {
+        LineEditor {
+            line: String::new(),
+            cursor: 0,
+            history: VecDeque::new(),
+            history_index: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Handles a key and returns the line once enter is pressed.
+    fn handle(&mut self, key: Key) -> Option<String> {
+        match key {
+            Key::Char(c) if c == ' ' || c.is_ascii_graphic() => self.insert(c),
+            Key::Char(_) => {}
+            Key::Backspace if self.cursor > 0 => {
+                self.move_left(1);
+                self.delete();
+            }
+            Key::Backspace => {}
+            Key::Delete => self.delete(),
+            Key::Left if self.cursor > 0 => self.move_left(1),
+            Key::Right if self.cursor < self.line.len() => {
+                cprint!("{}", &self.line[self.cursor..=self.cursor]);
+                self.cursor += 1;
+            }
+            Key::Left | Key::Right => {}
+            Key::Home => self.move_left(self.cursor),
+            Key::End => {
+                cprint!("{}", &self.line[self.cursor..]);
+                self.cursor = self.line.len();
+            }
+            Key::Up => self.browse_history(true),
+            Key::Down => self.browse_history(false),
+            Key::Enter => return Some(self.finish_line()),
+        }
+        None
+    }
+
+    fn insert(&mut self, c: char) {
+        self.line.insert(self.cursor, c);
+        cprint!("{}", &self.line[self.cursor..]);
+        self.cursor += 1;
+        print_backspaces(self.line.len() - self.cursor);
+    }
+
+    /// Removes the character under the cursor.
+    fn delete(&mut self) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+            cprint!("{} ", &self.line[self.cursor..]);
+            print_backspaces(self.line.len() - self.cursor + 1);
+        }
+    }
+
+    fn move_left(&mut self, count: usize) {
+        print_backspaces(count);
+        self.cursor -= count;
+    }
+
+    /// Replaces the displayed line.
+    fn set_line(&mut self, line: String) {
+        let old_length = self.line.len();
+        self.move_left(self.cursor);
+        for _ in 0..old_length {
+            cprint!(" ");
+        }
+        print_backspaces(old_length);
+        cprint!("{line}");
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    fn browse_history(&mut self, older: bool) {
+        let index = match (self.history_index, older) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = self.line.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(index), true) => Some(index.saturating_sub(1)),
+            (Some(index), false) if index + 1 < self.history.len() => Some(index + 1),
+            (Some(_), false) => None,
+            (None, _) => return,
+        };
+        self.history_index = index;
+        let line = match index {
+            Some(index) => self.history[index].clone(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.set_line(line);
+    }
+
+    fn finish_line(&mut self) -> String {
+        cprint!("\n");
+        let line = core::mem::take(&mut self.line);
+        self.cursor = 0;
+        self.history_index = None;
+        if !line.trim().is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_SIZE {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        line
+    }
+}
+
+fn print_backspaces(count: usize) {
+    for _ in 0..count {
+        cprint!("{BACKSPACE}");
+    }
+}
+
+/// Reads commands from the PS/2 keyboard and COM1 and runs them.
+pub async fn run() {
+    let mut events = input::subscribe(input::Filter::KEY | input::Filter::SERIAL);
+    let mut decoder = SerialDecoder::new();
+    let mut editor = LineEditor::new();
+    cprint!("{PROMPT}");
+    while let Some(event) = events.next().await {
+        let key = match event {
+            // the keyboard is focused on one console at a time
+            InputEvent::Key(event) if vga::active() == vga::SYSTEM_CONSOLE => {
+                decode_keyboard(event)
+            }
+            InputEvent::Serial(ComPort::Com1, byte) => decoder.decode(byte),
+            _ => None,
+        };
+        if let Some(line) = key.and_then(|key| editor.handle(key)) {
+            commands::run(&line);
+            cprint!("{PROMPT}");
+        }
+    }
+}
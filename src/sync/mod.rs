@@ -0,0 +1,66 @@
+pub mod irq_spinlock;
+pub mod mpsc;
+pub mod mutex;
+pub mod notify;
+pub mod oneshot;
+pub mod rwlock;
+pub mod semaphore;
+pub mod ticket_lock;
+
+pub use irq_spinlock::{IrqSpinlock, IrqSpinlockGuard};
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::{Event, Notified, Notify};
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{AcquireError, Semaphore, SemaphorePermit, TryAcquireError};
+pub use ticket_lock::{TicketLock, TicketLockGuard};
+
+#[cfg(feature = "lock-debug")]
+use core::panic::Location;
+#[cfg(feature = "lock-debug")]
+use crossbeam::atomic::AtomicCell;
+
+/// Remembers where a lock was taken when built with the `lock-debug`
+/// feature, and compiles to nothing otherwise.
+struct Holder {
+    #[cfg(feature = "lock-debug")]
+    location: AtomicCell<Option<&'static Location<'static>>>,
+}
+
+impl Holder {
+    const fn new() -> Self {
+        Holder {
+            #[cfg(feature = "lock-debug")]
+            location: AtomicCell::new(None),
+        }
+    }
+
+    #[track_caller]
+    fn acquired(&self) {
+        #[cfg(feature = "lock-debug")]
+        self.location.store(Some(Location::caller()));
+    }
+
+    fn released(&self) {
+        #[cfg(feature = "lock-debug")]
+        self.location.store(None);
+    }
+
+    /// Called before waiting for the lock. There is only one CPU and the
+    /// tasks are not preempted, so the holder can never release it.
+    #[track_caller]
+    fn contended(&self, kind: &str) {
+        #[cfg(feature = "lock-debug")]
+        match self.location.load() {
+            Some(holder) => panic!(
+                "deadlock: {kind} taken at {} is already held since {holder}",
+                Location::caller()
+            ),
+            None => panic!(
+                "deadlock: {kind} taken at {} is already held",
+                Location::caller()
+            ),
+        }
+        #[cfg(not(feature = "lock-debug"))]
+        let _ = kind;
+    }
+}
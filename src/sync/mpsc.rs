@@ -0,0 +1,165 @@
+use super::{IrqSpinlock, Semaphore, TryAcquireError};
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+use futures::{future::poll_fn, stream::Stream, task::AtomicWaker};
+
+struct Shared<T> {
+    /// Allocated with the full capacity, so sending never allocates.
+    queue: IrqSpinlock<VecDeque<T>>,
+    /// Free slots in the queue, closed when the receiver is gone.
+    slots: Semaphore,
+    senders: AtomicUsize,
+    receiver_waker: AtomicWaker,
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receives the values in the order they were sent, and `None` once all
+/// senders are gone and the queue is empty.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The receiver is gone, the value is returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Disconnected,
+}
+
+/// Creates a channel with many senders and one receiver, buffering up to
+/// `capacity` values.
+///
+/// Senders wait for a free slot, so a slow receiver slows down the senders
+/// instead of letting the queue grow.
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must not be zero");
+    let shared = Arc::new(Shared {
+        queue: IrqSpinlock::new(VecDeque::with_capacity(capacity)),
+        slots: Semaphore::new(capacity),
+        senders: AtomicUsize::new(1),
+        receiver_waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Waits for a free slot and queues `value`.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match self.shared.slots.acquire().await {
+            Ok(permit) => {
+                permit.forget();
+                self.push(value);
+                Ok(())
+            }
+            Err(_) => Err(SendError(value)),
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        match self.shared.slots.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                self.push(value);
+                Ok(())
+            }
+            Err(TryAcquireError::NoPermits) => Err(TrySendError::Full(value)),
+            Err(TryAcquireError::Closed) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    /// Whether the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.shared.slots.is_closed()
+    }
+
+    fn push(&self, value: T) {
+        self.shared.queue.lock().push_back(value);
+        self.shared.receiver_waker.wake();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.receiver_waker.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        // checked first, values sent before the last sender left are kept
+        let disconnected = self.shared.senders.load(Ordering::Acquire) == 0;
+        let value = self.shared.queue.lock().pop_front();
+        match value {
+            Some(value) => {
+                self.shared.slots.add_permits(1);
+                Ok(value)
+            }
+            None if disconnected => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        self.shared.receiver_waker.register(cx.waker());
+        match self.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Stops accepting new values, the queued ones can still be received.
+    pub fn close(&mut self) {
+        self.shared.slots.close();
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
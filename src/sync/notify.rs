@@ -0,0 +1,209 @@
+use super::IrqSpinlock;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use futures::task::AtomicWaker;
+
+const WAITING: u8 = 0;
+const NOTIFIED_ONE: u8 = 1;
+const NOTIFIED_ALL: u8 = 2;
+
+/// Wakes waiting tasks without passing data.
+///
+/// Notifying does not allocate, so interrupt handlers can wake tasks with it.
+pub struct Notify {
+    state: IrqSpinlock<State>,
+}
+
+struct State {
+    /// Stored by `notify_one` when no task waits.
+    permit: bool,
+    waiters: Vec<Arc<Waiter>>,
+}
+
+struct Waiter {
+    status: AtomicU8,
+    waker: AtomicWaker,
+}
+
+/// Future returned by [`Notify::notified`].
+///
+/// It starts waiting when first polled, notifications sent to all waiters
+/// before that are missed.
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Notify {
+    pub const fn new() -> Self {
+        Notify {
+            state: IrqSpinlock::new(State {
+                permit: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    /// Wakes the task that waits longest, or the next one to wait if there
+    /// is none.
+    pub fn notify_one(&self) {
+        self.state.lock().notify_one();
+    }
+
+    /// Wakes all tasks waiting right now.
+    pub fn notify_waiters(&self) {
+        let mut state = self.state.lock();
+        // the futures hold another reference, so this never deallocates
+        for waiter in state.waiters.drain(..) {
+            waiter.status.store(NOTIFIED_ALL, Ordering::Release);
+            waiter.waker.wake();
+        }
+    }
+
+    pub fn notified(&self) -> Notified {
+        Notified {
+            notify: self,
+            waiter: None,
+        }
+    }
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        if self.waiters.is_empty() {
+            self.permit = true;
+            return;
+        }
+        let waiter = self.waiters.remove(0);
+        waiter.status.store(NOTIFIED_ONE, Ordering::Release);
+        waiter.waker.wake();
+    }
+}
+
+impl Future for Notified<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        let waiter = match &this.waiter {
+            Some(waiter) => waiter,
+            None => {
+                let mut state = this.notify.state.lock();
+                if state.permit {
+                    state.permit = false;
+                    return Poll::Ready(());
+                }
+                let waiter = Arc::new(Waiter {
+                    status: AtomicU8::new(WAITING),
+                    waker: AtomicWaker::new(),
+                });
+                waiter.waker.register(cx.waker());
+                state.waiters.push(waiter.clone());
+                this.waiter = Some(waiter);
+                return Poll::Pending;
+            }
+        };
+        waiter.waker.register(cx.waker());
+        if waiter.status.load(Ordering::Acquire) == WAITING {
+            return Poll::Pending;
+        }
+        this.waiter = None;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Notified<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        let mut state = self.notify.state.lock();
+        match waiter.status.load(Ordering::Acquire) {
+            WAITING => state.waiters.retain(|other| !Arc::ptr_eq(other, &waiter)),
+            // don't lose the notification meant for one task
+            NOTIFIED_ONE => state.notify_one(),
+            _ => {}
+        }
+    }
+}
+
+/// A flag that tasks can wait for, e.g. set by an interrupt handler.
+pub struct Event {
+    set: AtomicBool,
+    notify: Notify,
+}
+
+/// Future returned by [`Event::wait`].
+pub struct Wait<'a> {
+    event: &'a Event,
+    notified: Notified<'a>,
+}
+
+impl Event {
+    pub const fn new() -> Self {
+        Event {
+            set: AtomicBool::new(false),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Sets the flag and wakes all waiting tasks.
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Release);
+        self.notify.notify_waiters();
+    }
+
+    pub fn clear(&self) {
+        self.set.store(false, Ordering::Release);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Acquire)
+    }
+
+    /// Waits until the flag is set, returns at once if it already is.
+    pub fn wait(&self) -> Wait {
+        Wait {
+            event: self,
+            notified: self.notify.notified(),
+        }
+    }
+}
+
+impl Default for Event {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Future for Wait<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            if this.event.is_set() {
+                return Poll::Ready(());
+            }
+            match Pin::new(&mut this.notified).poll(cx) {
+                // the flag may have been cleared again
+                Poll::Ready(()) => this.notified = this.event.notify.notified(),
+                // check again, it may have been set before we were registered
+                Poll::Pending if this.event.is_set() => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
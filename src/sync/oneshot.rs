@@ -0,0 +1,110 @@
+use super::IrqSpinlock;
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures::task::AtomicWaker;
+
+struct Inner<T> {
+    value: IrqSpinlock<Option<T>>,
+    /// The sender sent its value or was dropped.
+    complete: AtomicBool,
+    /// The receiver was dropped.
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// Future of the sent value, fails if the sender was dropped without sending.
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The sender was dropped without sending a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+}
+
+/// Creates a channel that sends a single value from one task to another.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        value: IrqSpinlock::new(None),
+        complete: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    (
+        Sender {
+            inner: inner.clone(),
+        },
+        Receiver { inner },
+    )
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, or returns it if the receiver is gone.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.is_closed() {
+            return Err(value);
+        }
+        *self.inner.value.lock() = Some(value);
+        // dropping `self` wakes the receiver
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.inner.closed.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.inner.complete.store(true, Ordering::Release);
+        self.inner.waker.wake();
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Whether the sender sent its value or was dropped.
+    pub fn is_complete(&self) -> bool {
+        self.inner.complete.load(Ordering::Acquire)
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        if !self.inner.complete.load(Ordering::Acquire) {
+            return Err(TryRecvError::Empty);
+        }
+        self.inner.value.lock().take().ok_or(TryRecvError::Closed)
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.inner.waker.register(cx.waker());
+        match this.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.closed.store(true, Ordering::Release);
+    }
+}
@@ -0,0 +1,224 @@
+use super::IrqSpinlock;
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+use futures::task::AtomicWaker;
+
+const WAITING: u8 = 0;
+const GRANTED: u8 = 1;
+const CLOSED: u8 = 2;
+
+/// Permits that tasks wait for without blocking the executor.
+///
+/// Waiters are served in the order they arrived, so a task asking for many
+/// permits is not starved by tasks asking for few. Adding permits and closing
+/// do not allocate and may be done by interrupt handlers.
+pub struct Semaphore {
+    state: IrqSpinlock<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    waiters: Vec<Arc<Waiter>>,
+}
+
+struct Waiter {
+    permits: usize,
+    status: AtomicU8,
+    waker: AtomicWaker,
+}
+
+/// The semaphore was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AcquireError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+/// Permits taken from a [`Semaphore`], they are given back when dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+/// Future returned by [`Semaphore::acquire_many`].
+///
+/// Dropping it gives up its place in the queue.
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    waiter: Option<Arc<Waiter>>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Semaphore {
+            state: IrqSpinlock::new(State {
+                permits,
+                closed: false,
+                waiters: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().permits
+    }
+
+    pub fn acquire(&self) -> Acquire {
+        self.acquire_many(1)
+    }
+
+    pub fn acquire_many(&self, permits: usize) -> Acquire {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiter: None,
+        }
+    }
+
+    pub fn try_acquire(&self) -> Result<SemaphorePermit, TryAcquireError> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes the permits if no task is waiting before us.
+    pub fn try_acquire_many(&self, permits: usize) -> Result<SemaphorePermit, TryAcquireError> {
+        let mut state = self.state.lock();
+        if state.closed {
+            Err(TryAcquireError::Closed)
+        } else if state.waiters.is_empty() && state.permits >= permits {
+            state.permits -= permits;
+            Ok(SemaphorePermit {
+                semaphore: self,
+                permits,
+            })
+        } else {
+            Err(TryAcquireError::NoPermits)
+        }
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let mut state = self.state.lock();
+        state.permits += permits;
+        state.grant();
+    }
+
+    /// Fails all pending and future acquisitions, permits can still be added
+    /// and released.
+    pub fn close(&self) {
+        let mut state = self.state.lock();
+        state.closed = true;
+        for waiter in state.waiters.drain(..) {
+            waiter.status.store(CLOSED, Ordering::Release);
+            waiter.waker.wake();
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().closed
+    }
+}
+
+impl State {
+    /// Hands the available permits to the waiters at the front.
+    fn grant(&mut self) {
+        while let Some(waiter) = self.waiters.first() {
+            if waiter.permits > self.permits {
+                break;
+            }
+            self.permits -= waiter.permits;
+            // the future holds another reference, so this never deallocates
+            let waiter = self.waiters.remove(0);
+            waiter.status.store(GRANTED, Ordering::Release);
+            waiter.waker.wake();
+        }
+    }
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Result<SemaphorePermit<'a>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let status = match &this.waiter {
+            Some(waiter) => {
+                waiter.waker.register(cx.waker());
+                waiter.status.load(Ordering::Acquire)
+            }
+            None => {
+                let mut state = this.semaphore.state.lock();
+                if state.closed {
+                    CLOSED
+                } else if state.waiters.is_empty() && state.permits >= this.permits {
+                    state.permits -= this.permits;
+                    GRANTED
+                } else {
+                    let waiter = Arc::new(Waiter {
+                        permits: this.permits,
+                        status: AtomicU8::new(WAITING),
+                        waker: AtomicWaker::new(),
+                    });
+                    waiter.waker.register(cx.waker());
+                    state.waiters.push(waiter.clone());
+                    this.waiter = Some(waiter);
+                    WAITING
+                }
+            }
+        };
+        match status {
+            GRANTED => {
+                this.waiter = None;
+                Poll::Ready(Ok(SemaphorePermit {
+                    semaphore: this.semaphore,
+                    permits: this.permits,
+                }))
+            }
+            CLOSED => {
+                this.waiter = None;
+                Poll::Ready(Err(AcquireError))
+            }
+            _ => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let waiter = match self.waiter.take() {
+            Some(waiter) => waiter,
+            None => return,
+        };
+        // the status only changes with the lock held
+        let mut state = self.semaphore.state.lock();
+        match waiter.status.load(Ordering::Acquire) {
+            GRANTED => state.permits += waiter.permits,
+            WAITING => state.waiters.retain(|other| !Arc::ptr_eq(other, &waiter)),
+            _ => return,
+        }
+        // the waiters behind us may be satisfied now
+        state.grant();
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits taken, e.g. to hand them over to an interrupt
+    /// handler that adds them back.
+    pub fn forget(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
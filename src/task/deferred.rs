@@ -0,0 +1,72 @@
+use conquer_once::spin::OnceCell;
+use core::sync::atomic::{AtomicU64, Ordering};
+use crossbeam::queue::ArrayQueue;
+
+/// How many work items can be pending before new ones are dropped.
+const QUEUE_SIZE: usize = 256;
+
+/// Work queued from interrupt context and run by the executor.
+#[derive(Debug, Clone, Copy)]
+pub enum Work {
+    /// Calls the function with the given argument.
+    Call(fn(usize), usize),
+    /// Logs the message as a warning.
+    Log(&'static str),
+}
+
+static QUEUE: OnceCell<ArrayQueue<Work>> = OnceCell::uninit();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Allocates the work queue, requires the heap.
+pub fn init() {
+    QUEUE.init_once(|| ArrayQueue::new(QUEUE_SIZE));
+}
+
+/// Queues `work` to run in task context.
+///
+/// Does not block or allocate, so it can be called from interrupt handlers.
+/// Returns `false` if the work was dropped because the queue is full or not
+/// yet initialized.
+pub fn defer(work: Work) -> bool {
+    let queued = match QUEUE.get() {
+        Some(queue) => queue.push(work).is_ok(),
+        None => false,
+    };
+    if !queued {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+    queued
+}
+
+/// Queues a call of `function` with `argument`.
+pub fn defer_call(function: fn(usize), argument: usize) -> bool {
+    defer(Work::Call(function, argument))
+}
+
+/// Logs `message` from interrupt context without touching the output locks.
+pub fn log(message: &'static str) -> bool {
+    defer(Work::Log(message))
+}
+
+/// Number of work items dropped since boot.
+pub fn dropped() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+pub fn is_empty() -> bool {
+    QUEUE.get().map_or(true, |queue| queue.is_empty())
+}
+
+/// Runs all pending work, called by the executor.
+pub fn run_pending() {
+    let queue = match QUEUE.get() {
+        Some(queue) => queue,
+        None => return,
+    };
+    while let Some(work) = queue.pop() {
+        match work {
+            Work::Call(function, argument) => function(argument),
+            Work::Log(message) => log::warn!("{message}"),
+        }
+    }
+}
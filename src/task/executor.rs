@@ -1,66 +1,227 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
-use core::task::{Context, Poll, Waker};
-use crossbeam::queue::ArrayQueue;
+use super::{deferred, JoinHandle, Priority, Task, TaskId};
+use crate::sync::IrqSpinlock;
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+    task::Wake,
+    vec::Vec,
+};
+use core::{
+    future::Future,
+    pin::Pin,
+    ptr, slice, str,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
+use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::instructions::interrupts;
 
+/// Information about a spawned task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: u64,
+    pub name: Option<&'static str>,
+    pub priority: Priority,
+    pub polls: u64,
+}
+
+/// Polls each priority gets per round. A round ends once the budgets of all
+/// ready tasks are used up, so a task that keeps waking itself cannot hold
+/// off deferred work, newly spawned tasks or tasks of lower priority.
+const BUDGETS: [usize; Priority::COUNT] = [32, 16, 4];
+
+lazy_static! {
+    /// Mirrors the tasks of the executor, so they can be listed from tasks.
+    static ref TASK_INFO: Mutex<BTreeMap<TaskId, TaskInfo>> = Mutex::new(BTreeMap::new());
+}
+
+/// Id of the task being polled, for crash reports. [`NO_TASK`] while the
+/// executor itself runs. Written last, so the name below is complete once the
+/// id is set.
+static CURRENT_ID: AtomicU64 = AtomicU64::new(NO_TASK);
+/// Name of the task being polled, null if it has none.
+static CURRENT_NAME: AtomicPtr<u8> = AtomicPtr::new(ptr::null_mut());
+static CURRENT_NAME_LEN: AtomicUsize = AtomicUsize::new(0);
+const NO_TASK: u64 = u64::MAX;
+/// Tasks passed to [`spawn`], picked up by the executor on its next round.
+static SPAWNED: Mutex<Vec<Spawned>> = Mutex::new(Vec::new());
+
+/// Lists the tasks that did not finish yet.
+pub fn tasks() -> Vec<TaskInfo> {
+    TASK_INFO.lock().values().copied().collect()
+}
+
+/// Id and name of the task being polled, `None` while the executor itself
+/// runs. Lock-free, so it can be used while panicking.
+pub fn current() -> Option<(u64, Option<&'static str>)> {
+    let id = CURRENT_ID.load(Ordering::Acquire);
+    if id == NO_TASK {
+        return None;
+    }
+    let name = CURRENT_NAME.load(Ordering::Relaxed);
+    let len = CURRENT_NAME_LEN.load(Ordering::Relaxed);
+    // SAFETY: the pointer and length are taken from a `&'static str` before
+    // the id is published
+    let name = (!name.is_null())
+        .then(|| unsafe { str::from_utf8_unchecked(slice::from_raw_parts(name, len)) });
+    Some((id, name))
+}
+
+fn set_current(info: Option<&TaskInfo>) {
+    CURRENT_ID.store(NO_TASK, Ordering::Release);
+    if let Some(info) = info {
+        let (pointer, len) = match info.name {
+            Some(name) => (name.as_ptr() as *mut u8, name.len()),
+            None => (ptr::null_mut(), 0),
+        };
+        CURRENT_NAME.store(pointer, Ordering::Relaxed);
+        CURRENT_NAME_LEN.store(len, Ordering::Relaxed);
+        CURRENT_ID.store(info.id, Ordering::Release);
+    }
+}
+
+/// Spawns a task from other tasks or deferred work, not from interrupt
+/// handlers because it allocates.
+pub fn spawn<T>(task: Task<T>) -> JoinHandle<T> {
+    let (spawned, handle) = register(task);
+    SPAWNED.lock().push(spawned);
+    handle
+}
+
+/// A task the executor did not pick up yet.
+struct Spawned {
+    id: TaskId,
+    priority: Priority,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+// there is one CPU and all tasks run on the executor, so a task is never
+// polled from two places at once
+unsafe impl Send for Spawned {}
+
+/// Lists the task and creates its handle.
+fn register<T>(task: Task<T>) -> (Spawned, JoinHandle<T>) {
+    TASK_INFO.lock().insert(
+        task.id,
+        TaskInfo {
+            id: task.id.0,
+            name: task.name,
+            priority: task.priority,
+            polls: 0,
+        },
+    );
+    let spawned = Spawned {
+        id: task.id,
+        priority: task.priority,
+        future: task.future,
+    };
+    (spawned, JoinHandle::new(task.id, task.name, task.state))
+}
+
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    tasks: BTreeMap<TaskId, Pin<Box<dyn Future<Output = ()>>>>,
+    run_queue: Arc<RunQueue>,
+    waker_cache: BTreeMap<TaskId, (Arc<TaskWaker>, Waker)>,
+}
+
+/// The tasks ready to run by priority, each queued at most once.
+///
+/// Room for every task is reserved when it is spawned, so waking a task
+/// never allocates and can be done by interrupt handlers.
+struct RunQueue {
+    ids: IrqSpinlock<[VecDeque<TaskId>; Priority::COUNT]>,
 }
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    priority: Priority,
+    /// Set while the task is in the run queue, and for good once it finished.
+    queued: AtomicBool,
+    run_queue: Arc<RunQueue>,
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
+            run_queue: Arc::new(RunQueue::new()),
             waker_cache: BTreeMap::new(),
         }
     }
 
-    pub fn spawn(&mut self, task: Task) {
-        let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
-            panic!("task with same ID already in tasks");
-        }
-        self.task_queue.push(task_id).expect("queue full");
+    pub fn spawn<T>(&mut self, task: Task<T>) -> JoinHandle<T> {
+        let (spawned, handle) = register(task);
+        self.insert(spawned);
+        handle
     }
 
     pub fn run(&mut self) -> ! {
         loop {
+            deferred::run_pending();
+            self.insert_spawned();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
     }
 
+    fn insert(&mut self, task: Spawned) {
+        if self.tasks.insert(task.id, task.future).is_some() {
+            panic!("task with same ID already in tasks");
+        }
+        self.run_queue.reserve(self.tasks.len());
+        let task_waker = Arc::new(TaskWaker {
+            task_id: task.id,
+            priority: task.priority,
+            queued: AtomicBool::new(false),
+            run_queue: self.run_queue.clone(),
+        });
+        task_waker.wake_task();
+        let waker = Waker::from(task_waker.clone());
+        self.waker_cache.insert(task.id, (task_waker, waker));
+    }
+
+    /// Takes over the tasks passed to [`spawn`].
+    fn insert_spawned(&mut self) {
+        let spawned = core::mem::take(&mut *SPAWNED.lock());
+        for task in spawned {
+            self.insert(task);
+        }
+    }
+
     fn run_ready_tasks(&mut self) {
         // destructure `self` to avoid borrow checker errors
         let Self {
             tasks,
-            task_queue,
+            run_queue,
             waker_cache,
         } = self;
 
-        while let Some(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
-                None => continue, // task no longer exists
-            };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
+        let mut budgets = BUDGETS;
+        while let Some(task_id) = run_queue.pop(&mut budgets) {
+            let (task, (task_waker, waker)) =
+                match (tasks.get_mut(&task_id), waker_cache.get(&task_id)) {
+                    (Some(task), Some(waker)) => (task, waker),
+                    _ => continue, // task no longer exists
+                };
+            // wakeups from now on queue the task again
+            task_waker.queued.store(false, Ordering::Release);
             let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+            if let Some(info) = TASK_INFO.lock().get_mut(&task_id) {
+                info.polls += 1;
+                set_current(Some(info));
+            }
+            let poll = task.as_mut().poll(&mut context);
+            set_current(None);
+            match poll {
                 Poll::Ready(()) => {
                     // task done -> remove it and its cached waker
                     tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+                    if let Some((task_waker, _)) = waker_cache.remove(&task_id) {
+                        task_waker.finish();
+                    }
+                    TASK_INFO.lock().remove(&task_id);
                 }
                 Poll::Pending => {}
             }
@@ -70,7 +231,7 @@ impl Executor {
     fn sleep_if_idle(&self) {
         // TODO: use an idle Task to do some work
         interrupts::disable();
-        if self.task_queue.is_empty() {
+        if self.run_queue.is_empty() && deferred::is_empty() && SPAWNED.lock().is_empty() {
             interrupts::enable_and_hlt();
         } else {
             interrupts::enable();
@@ -78,16 +239,66 @@ impl Executor {
     }
 }
 
-impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunQueue {
+    fn new() -> Self {
+        RunQueue {
+            ids: IrqSpinlock::new([(); Priority::COUNT].map(|_| VecDeque::new())),
+        }
     }
 
+    /// Makes room for `tasks` tasks in every queue, called in task context.
+    fn reserve(&self, tasks: usize) {
+        for ids in self.ids.lock().iter_mut() {
+            let length = ids.len();
+            ids.reserve(tasks.saturating_sub(length));
+        }
+    }
+
+    fn push(&self, task_id: TaskId, priority: Priority) {
+        let mut queues = self.ids.lock();
+        let ids = &mut queues[priority as usize];
+        debug_assert!(ids.len() < ids.capacity(), "run queue would grow");
+        ids.push_back(task_id);
+    }
+
+    /// Takes the next task of the highest priority with budget left.
+    fn pop(&self, budgets: &mut [usize; Priority::COUNT]) -> Option<TaskId> {
+        let mut queues = self.ids.lock();
+        let (ids, budget) = queues
+            .iter_mut()
+            .zip(budgets.iter_mut())
+            .find(|(ids, budget)| **budget > 0 && !ids.is_empty())?;
+        *budget -= 1;
+        ids.pop_front()
+    }
+
+    fn remove(&self, task_id: TaskId, priority: Priority) {
+        self.ids.lock()[priority as usize].retain(|&id| id != task_id);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ids.lock().iter().all(|ids| ids.is_empty())
+    }
+}
+
+impl TaskWaker {
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.run_queue.push(self.task_id, self.priority);
+        }
+    }
+
+    /// Stops queueing the finished task, wakers may outlive it.
+    fn finish(&self) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            self.run_queue.remove(self.task_id, self.priority);
+        }
     }
 }
 
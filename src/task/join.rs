@@ -0,0 +1,129 @@
+use super::TaskId;
+use crate::sync::oneshot;
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+use futures::task::AtomicWaker;
+
+/// Why a task has no output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Cancelled,
+}
+
+/// Lets a [`JoinHandle`] cancel its task.
+struct Abort {
+    aborted: AtomicBool,
+    /// Wakes the task, so it notices that it was aborted.
+    task_waker: AtomicWaker,
+}
+
+/// The parts of a [`JoinHandle`] that are created with the task.
+pub(super) struct JoinState<T> {
+    receiver: oneshot::Receiver<Result<T, JoinError>>,
+    abort: Arc<Abort>,
+}
+
+/// Future of a spawned task's output.
+///
+/// Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    name: Option<&'static str>,
+    state: JoinState<T>,
+}
+
+/// Runs the future of a task and sends its output to the [`JoinHandle`].
+pub(super) struct Joined<F: Future> {
+    future: F,
+    sender: Option<oneshot::Sender<Result<F::Output, JoinError>>>,
+    abort: Arc<Abort>,
+}
+
+/// Wraps `future` for the executor and returns the state of its handle.
+pub(super) fn new<F: Future>(future: F) -> (Joined<F>, JoinState<F::Output>) {
+    let (sender, receiver) = oneshot::channel();
+    let abort = Arc::new(Abort {
+        aborted: AtomicBool::new(false),
+        task_waker: AtomicWaker::new(),
+    });
+    let joined = Joined {
+        future,
+        sender: Some(sender),
+        abort: abort.clone(),
+    };
+    (joined, JoinState { receiver, abort })
+}
+
+impl<F: Future> Joined<F> {
+    fn finish(&mut self, result: Result<F::Output, JoinError>) {
+        if let Some(sender) = self.sender.take() {
+            // the handle may have been dropped
+            sender.send(result).ok();
+        }
+    }
+}
+
+impl<F: Future> Future for Joined<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // SAFETY: `future` is never moved out of the pinned `Joined`
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.abort.aborted.load(Ordering::Acquire) {
+            this.finish(Err(JoinError::Cancelled));
+            return Poll::Ready(());
+        }
+        this.abort.task_waker.register(cx.waker());
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match future.poll(cx) {
+            Poll::Ready(output) => {
+                this.finish(Ok(output));
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    pub(super) fn new(id: TaskId, name: Option<&'static str>, state: JoinState<T>) -> Self {
+        JoinHandle { id, name, state }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id.0
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.receiver.is_complete()
+    }
+
+    /// Cancels the task, its future is dropped the next time the executor
+    /// gets to it. Awaiting the handle then returns [`JoinError::Cancelled`].
+    pub fn abort(&self) {
+        if !self.is_finished() {
+            self.state.abort.aborted.store(true, Ordering::Release);
+            self.state.abort.task_waker.wake();
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // a task dropped without output counts as cancelled
+        Pin::new(&mut self.get_mut().state.receiver)
+            .poll(cx)
+            .map(|result| result.unwrap_or(Err(JoinError::Cancelled)))
+    }
+}
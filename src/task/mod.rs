@@ -1,7 +1,11 @@
-pub mod keyboard;
+pub mod deferred;
 pub mod executor;
+mod join;
 pub mod timer;
 
+pub use executor::spawn;
+pub use join::{JoinError, JoinHandle};
+
 use alloc::boxed::Box;
 use core::{
     future::Future,
@@ -9,14 +13,30 @@ use core::{
     sync::atomic::{AtomicU64, Ordering},
     task::{Context, Poll},
 };
+use join::JoinState;
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 struct TaskId(u64);
 
-pub struct Task {
+/// Decides which of the ready tasks is polled first and how many polls each
+/// gets per round of the executor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Polled before all others, e.g. input handling.
+    Interactive,
+    Normal,
+    /// Gets a small share while other tasks are ready.
+    Background,
+}
+
+/// A future to spawn, its output is returned through the [`JoinHandle`].
+pub struct Task<T> {
     id: TaskId,
+    name: Option<&'static str>,
+    priority: Priority,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    state: JoinState<T>,
 }
 
 impl TaskId {
@@ -26,16 +46,41 @@ impl TaskId {
     }
 }
 
-impl Task {
-    pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+impl<T: 'static> Task<T> {
+    pub fn new(future: impl Future<Output = T> + 'static) -> Task<T> {
+        let (joined, state) = join::new(future);
         Task {
             id: TaskId::new(),
-            future: Box::pin(future),
+            name: None,
+            priority: Priority::Normal,
+            future: Box::pin(joined),
+            state,
         }
     }
 
-    fn poll(&mut self, context: &mut Context) -> Poll<()> {
-        self.future.as_mut().poll(context)
+    /// Creates a task whose name is shown in the task list and crash
+    /// reports.
+    pub fn named(name: &'static str, future: impl Future<Output = T> + 'static) -> Task<T> {
+        Task {
+            name: Some(name),
+            ..Task::new(future)
+        }
+    }
+
+    pub fn with_priority(self, priority: Priority) -> Task<T> {
+        Task { priority, ..self }
+    }
+}
+
+impl Priority {
+    const COUNT: usize = 3;
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Normal => "normal",
+            Priority::Background => "background",
+        }
     }
 }
 
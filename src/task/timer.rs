@@ -1,34 +1,87 @@
-use conquer_once::spin::OnceCell;
+use alloc::collections::BTreeMap;
 use core::{
     future::Future,
     pin::Pin,
     task::{Context, Poll, Waker},
+    time::Duration,
 };
-use crossbeam::{atomic::AtomicCell, queue::ArrayQueue};
+use crossbeam::atomic::AtomicCell;
+use futures::stream::{Stream, StreamExt};
+use lazy_static::lazy_static;
 
-use crate::vga;
+use crate::sync::IrqSpinlock;
+use crate::{time, vga};
 
-static WAKERS: OnceCell<ArrayQueue<Waker>> = OnceCell::uninit();
+/// Pending timers, keyed by deadline and id.
+///
+/// Entries are only removed by their [`Sleeper`], so `tick` never frees
+/// memory in interrupt context.
+struct Timers {
+    pending: BTreeMap<(usize, usize), Waker>,
+    /// Tick up to which all timers were woken.
+    woken: usize,
+}
+
+lazy_static! {
+    /// Only locked with interrupts disabled, so `tick` can never interrupt a
+    /// holder of the lock.
+    static ref TIMERS: IrqSpinlock<Timers> = IrqSpinlock::new(Timers {
+        pending: BTreeMap::new(),
+        woken: 0,
+    });
+}
 static TICKS: AtomicCell<usize> = AtomicCell::new(0);
+static NEXT_ID: AtomicCell<usize> = AtomicCell::new(0);
+
+/// Called by the timer interrupt handler
+///
+/// Wakes all timers whose deadline has passed. Must not block or allocate.
 pub fn tick() {
-    TICKS.fetch_add(1);
-    if let Ok(wakers) = WAKERS.try_get() {
-        while let Some(waker) = wakers.pop() {
-            waker.wake();
+    let now = TICKS.fetch_add(1) + 1;
+    // expired timers are picked up on the next tick if the lock is taken
+    if let Some(mut timers) = TIMERS.try_lock() {
+        let expired = (timers.woken + 1, 0)..=(now, usize::MAX);
+        for waker in timers.pending.range(expired).map(|(_, waker)| waker) {
+            waker.wake_by_ref();
         }
+        timers.woken = now;
     }
-    
 }
 
+/// Number of system ticks since the timer was started.
+pub fn ticks() -> usize {
+    TICKS.load()
+}
+
+/// Queues a timer and returns the id to remove it with.
+fn register(deadline: usize, waker: Waker) -> usize {
+    let id = NEXT_ID.fetch_add(1);
+    TIMERS.lock().pending.insert((deadline, id), waker);
+    id
+}
+
+/// Future that completes once the tick counter reaches its target.
+///
+/// Dropping a sleeper removes its timer.
 pub struct Sleeper {
     target_ticks: usize,
+    /// The waker we registered a timer for and the id of that timer.
+    timer: Option<(Waker, usize)>,
 }
 
 impl Sleeper {
-    fn new(ticks: usize) -> Self {
-        WAKERS.get_or_init(|| ArrayQueue::new(100));
+    fn new(target_ticks: usize) -> Self {
         Self {
-            target_ticks: TICKS.load() + ticks,
+            target_ticks,
+            timer: None,
+        }
+    }
+}
+
+impl Drop for Sleeper {
+    fn drop(&mut self) {
+        if let Some((_, id)) = self.timer.take() {
+            TIMERS.lock().pending.remove(&(self.target_ticks, id));
         }
     }
 }
@@ -36,31 +89,121 @@ impl Sleeper {
 impl Future for Sleeper {
     type Output = ();
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-        if TICKS.load() >= self.target_ticks {
+        let this = self.get_mut();
+        if TICKS.load() >= this.target_ticks {
             return Poll::Ready(());
         }
-        WAKERS.try_get().expect("uninited").push(cx.waker().clone()).unwrap();
-        if TICKS.load() >= self.target_ticks {
-            return Poll::Ready(());
+        match &mut this.timer {
+            // only update the timer if we are polled from a different task
+            Some((waker, id)) => {
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                    let key = (this.target_ticks, *id);
+                    TIMERS.lock().pending.insert(key, cx.waker().clone());
+                }
+            }
+            None => {
+                let id = register(this.target_ticks, cx.waker().clone());
+                this.timer = Some((cx.waker().clone(), id));
+            }
+        }
+        if TICKS.load() >= this.target_ticks {
+            Poll::Ready(())
         } else {
-            return Poll::Pending;
+            Poll::Pending
         }
     }
 }
 
+/// Sleeps for the given number of ticks.
 pub fn sleep(ticks: usize) -> Sleeper {
-    return Sleeper::new(ticks);
+    Sleeper::new(TICKS.load() + ticks)
+}
+
+/// Sleeps until the tick counter reaches `deadline`.
+pub fn sleep_until(deadline: usize) -> Sleeper {
+    Sleeper::new(deadline)
+}
+
+/// Error returned by [`timeout`] if the deadline passed first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+pub struct Timeout<F> {
+    future: F,
+    sleeper: Sleeper,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of the pinned `Timeout`,
+        // `sleeper` is `Unpin`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleeper).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Runs `future` and gives up with `Err(Elapsed)` after `duration`.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleeper: sleep(time::duration_to_ticks(duration)),
+    }
+}
+
+/// Stream that yields the tick of every elapsed period.
+///
+/// Deadlines are computed from the start, not from the time the last item
+/// was consumed, so a slow consumer does not make the interval drift.
+pub struct Interval {
+    period: usize,
+    sleeper: Sleeper,
+}
+
+impl Stream for Interval {
+    type Item = usize;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<usize>> {
+        match Pin::new(&mut self.sleeper).poll(cx) {
+            Poll::Ready(()) => {
+                let deadline = self.sleeper.target_ticks;
+                self.sleeper = sleep_until(deadline + self.period);
+                Poll::Ready(Some(deadline))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Creates an [`Interval`] whose first item is ready one `period` from now.
+pub fn interval(period: Duration) -> Interval {
+    let period = time::duration_to_ticks(period).max(1);
+    Interval {
+        period,
+        sleeper: sleep(period),
+    }
 }
 
 const INDICATOR: [char; 4] = ['\\', '|', '/', '-'];
 pub async fn indicator() {
     let mut index = 0;
-    loop {
+    let mut interval = interval(Duration::from_millis(50));
+    while interval.next().await.is_some() {
         index += 1;
         if index >= INDICATOR.len() {
             index = 0;
         }
-        vga::WRITER.lock().write_at(INDICATOR[index] as u8, 0, 79);
-        sleep(1).await;
+        vga::CONSOLES[vga::SYSTEM_CONSOLE]
+            .lock()
+            .write_at(INDICATOR[index] as u8, 0, 79);
     }
 }
@@ -0,0 +1,246 @@
+use super::TICK_HZ;
+use crate::interrupts::{register_irq, InterruptVectors, IrqReturn};
+use crate::{acpi, acpi::SdtHeader, memory};
+use conquer_once::spin::OnceCell;
+use core::{
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use crossbeam::atomic::AtomicCell;
+use x86_64::{instructions::interrupts, PhysAddr, VirtAddr};
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+/// Longest counter period the specification allows, 100 ns.
+const MAX_PERIOD: u64 = 0x05f5_e100;
+
+const GENERAL_CAPABILITIES: usize = 0x000;
+const GENERAL_CONFIGURATION: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+
+const fn timer_configuration(timer: usize) -> usize {
+    0x100 + 0x20 * timer
+}
+
+const fn timer_comparator(timer: usize) -> usize {
+    0x108 + 0x20 * timer
+}
+
+// general capabilities and configuration bits
+const COUNT_SIZE_CAP: u64 = 1 << 13;
+const LEG_RT_CAP: u64 = 1 << 15;
+const ENABLE_CNF: u64 = 1 << 0;
+const LEG_RT_CNF: u64 = 1 << 1;
+
+// timer configuration bits
+const TN_INT_ENB_CNF: u64 = 1 << 2;
+const TN_TYPE_CNF: u64 = 1 << 3;
+const TN_PER_INT_CAP: u64 = 1 << 4;
+const TN_VAL_SET_CNF: u64 = 1 << 6;
+
+/// In legacy replacement mode timer 0 is routed to IRQ 0 and timer 1 to IRQ 8.
+const TICK_TIMER: usize = 0;
+const ONESHOT_TIMER: usize = 1;
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct HpetTable {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    address_space_id: u8,
+    register_bit_width: u8,
+    register_bit_offset: u8,
+    reserved: u8,
+    address: u64,
+    hpet_number: u8,
+    minimum_tick: u16,
+    page_protection: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpetError {
+    NotPresent,
+    /// The HPET can not deliver interrupts without an I/O APIC.
+    Unsupported,
+    /// Legacy replacement routing is off, see [`enable_legacy_routing`].
+    NotRouted,
+    /// IRQ 8 is used by the periodic RTC interrupt.
+    IrqInUse,
+}
+
+struct Hpet {
+    base: VirtAddr,
+    /// Length of one counter tick in femtoseconds.
+    period: u64,
+    minimum_tick: u64,
+    /// Whether legacy replacement routing is available.
+    legacy_capable: bool,
+    /// Whether the main counter is 64 bits wide.
+    wide_counter: bool,
+}
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+static ONESHOT_HANDLER: AtomicCell<Option<fn()>> = AtomicCell::new(None);
+static LEGACY_ROUTING: AtomicBool = AtomicBool::new(false);
+
+impl Hpet {
+    fn read(&self, register: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base + register).as_ptr()) }
+    }
+
+    fn write(&self, register: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base + register).as_mut_ptr(), value) }
+    }
+
+    fn ticks_to_duration(&self, ticks: u64) -> Duration {
+        let nanos = u128::from(ticks) * u128::from(self.period) / 1_000_000;
+        Duration::from_nanos(nanos as u64)
+    }
+
+    fn duration_to_ticks(&self, duration: Duration) -> u64 {
+        (duration.as_nanos() * 1_000_000 / u128::from(self.period)) as u64
+    }
+}
+
+/// Looks up the HPET in the ACPI tables and starts its main counter.
+///
+/// Interrupts stay off, so IRQ 0 and 8 are left to the PIT and the RTC until
+/// [`enable_legacy_routing`] is called. Returns `false` if no usable HPET
+/// exists.
+pub fn init() -> bool {
+    let table: HpetTable = match acpi::find_table(b"HPET") {
+        Some(addr) => unsafe { acpi::read(addr) },
+        None => return false,
+    };
+    // only system memory is a valid address space for the HPET
+    if table.address_space_id != 0 {
+        return false;
+    }
+    let base = match memory::map_mmio(PhysAddr::new(table.address), 0x400) {
+        Ok(base) => base,
+        Err(_) => return false,
+    };
+
+    let mut hpet = Hpet {
+        base,
+        period: 0,
+        minimum_tick: u64::from(table.minimum_tick).max(1),
+        legacy_capable: false,
+        wide_counter: false,
+    };
+    let capabilities = hpet.read(GENERAL_CAPABILITIES);
+    let last_timer = ((capabilities >> 8) & 0x1f) as usize;
+    hpet.period = capabilities >> 32;
+    if !(1..=MAX_PERIOD).contains(&hpet.period) {
+        return false;
+    }
+    hpet.wide_counter = capabilities & COUNT_SIZE_CAP != 0;
+
+    // halt and reset the main counter while we configure the timers
+    let configuration = hpet.read(GENERAL_CONFIGURATION) & !(ENABLE_CNF | LEG_RT_CNF);
+    hpet.write(GENERAL_CONFIGURATION, configuration);
+    hpet.write(MAIN_COUNTER, 0);
+
+    let tick_timer = hpet.read(timer_configuration(TICK_TIMER));
+    hpet.legacy_capable = capabilities & LEG_RT_CAP != 0
+        && last_timer >= ONESHOT_TIMER
+        && tick_timer & TN_PER_INT_CAP != 0;
+    hpet.write(timer_configuration(TICK_TIMER), 0);
+    hpet.write(timer_configuration(ONESHOT_TIMER), 0);
+
+    hpet.write(GENERAL_CONFIGURATION, configuration | ENABLE_CNF);
+    HPET.init_once(|| hpet);
+    true
+}
+
+/// Switches to legacy replacement routing: timer 0 takes over the system
+/// tick on IRQ 0 from the PIT and timer 1 provides one-shot interrupts on
+/// IRQ 8, which the periodic RTC interrupt can not use from then on.
+pub fn enable_legacy_routing() -> Result<(), HpetError> {
+    let hpet = HPET.get().ok_or(HpetError::NotPresent)?;
+    if !hpet.legacy_capable {
+        return Err(HpetError::Unsupported);
+    }
+    interrupts::without_interrupts(|| {
+        if super::rtc::is_periodic_enabled() {
+            return Err(HpetError::IrqInUse);
+        }
+        if LEGACY_ROUTING.swap(true, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let period = FEMTOSECONDS_PER_SECOND / (hpet.period * TICK_HZ);
+        hpet.write(
+            timer_configuration(TICK_TIMER),
+            TN_INT_ENB_CNF | TN_TYPE_CNF | TN_VAL_SET_CNF,
+        );
+        // first write sets the comparator, the second one the period
+        let first = hpet.read(MAIN_COUNTER).wrapping_add(period);
+        hpet.write(timer_comparator(TICK_TIMER), first);
+        hpet.write(timer_comparator(TICK_TIMER), period);
+        let configuration = hpet.read(GENERAL_CONFIGURATION);
+        hpet.write(GENERAL_CONFIGURATION, configuration | LEG_RT_CNF);
+        Ok(())
+    })?;
+    register_irq(InterruptVectors::Rtc.irq(), "hpet", handle_interrupt)
+        .expect("registering the HPET failed");
+    Ok(())
+}
+
+/// Whether the HPET generates the system tick on IRQ 0 and owns IRQ 8.
+pub fn is_tick_source() -> bool {
+    LEGACY_ROUTING.load(Ordering::Relaxed)
+}
+
+/// Whether the main counter can serve as clocksource.
+///
+/// A 32-bit counter wraps after about 5 minutes at the common 14.3 MHz, so
+/// only a 64-bit counter is used for the uptime. The comparators still work
+/// with 32 bits, the HPET can drive the tick either way.
+pub fn is_clocksource() -> bool {
+    HPET.get().map_or(false, |hpet| hpet.wide_counter)
+}
+
+/// Current value of the main counter.
+pub fn counter() -> Option<u64> {
+    HPET.get().map(|hpet| hpet.read(MAIN_COUNTER))
+}
+
+/// Time since the HPET was initialized, `None` if there is no HPET or its
+/// counter is only 32 bits wide.
+pub fn elapsed() -> Option<Duration> {
+    HPET.get()
+        .filter(|hpet| hpet.wide_counter)
+        .map(|hpet| hpet.ticks_to_duration(hpet.read(MAIN_COUNTER)))
+}
+
+/// Calls `handler` once after `delay` has passed.
+///
+/// The handler runs in interrupt context, so it must not block or allocate.
+/// Arming the timer again replaces a pending handler. Requires
+/// [`enable_legacy_routing`].
+pub fn set_oneshot(delay: Duration, handler: fn()) -> Result<(), HpetError> {
+    let hpet = HPET.get().ok_or(HpetError::NotPresent)?;
+    if !is_tick_source() {
+        return Err(HpetError::NotRouted);
+    }
+    let ticks = hpet.duration_to_ticks(delay).max(hpet.minimum_tick);
+    interrupts::without_interrupts(|| {
+        ONESHOT_HANDLER.store(Some(handler));
+        hpet.write(timer_configuration(ONESHOT_TIMER), TN_INT_ENB_CNF);
+        let deadline = hpet.read(MAIN_COUNTER).wrapping_add(ticks);
+        hpet.write(timer_comparator(ONESHOT_TIMER), deadline);
+    });
+    Ok(())
+}
+
+/// IRQ 8 handler while legacy replacement routing is active.
+fn handle_interrupt(_vector: u8) -> IrqReturn {
+    match ONESHOT_HANDLER.swap(None) {
+        Some(handler) => {
+            handler();
+            IrqReturn::Handled
+        }
+        None => IrqReturn::NotHandled,
+    }
+}
@@ -0,0 +1,87 @@
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+
+use crate::{interrupts, interrupts::InterruptVectors, interrupts::IrqReturn, task};
+use conquer_once::spin::OnceCell;
+use core::time::Duration;
+use rtc::DateTime;
+
+/// Frequency of the system tick that drives `task::timer`.
+pub const TICK_HZ: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    Hpet,
+    Pit,
+}
+
+impl ClockSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            ClockSource::Hpet => "hpet",
+            ClockSource::Pit => "pit",
+        }
+    }
+}
+
+static CLOCKSOURCE: OnceCell<ClockSource> = OnceCell::uninit();
+/// Unix time at which the monotonic clock started.
+static BOOT_TIME: OnceCell<Duration> = OnceCell::uninit();
+
+/// Selects the HPET as clocksource if there is one with a 64-bit counter and
+/// falls back to the PIT.
+///
+/// Requires ACPI and memory to be initialized.
+pub fn init() {
+    let source = if hpet::init() && hpet::is_clocksource() {
+        ClockSource::Hpet
+    } else {
+        ClockSource::Pit
+    };
+    // the HPET only takes over the tick once legacy routing is enabled
+    pit::init(TICK_HZ);
+    interrupts::register_irq(InterruptVectors::Timer.irq(), "timer", tick)
+        .expect("registering the timer failed");
+    CLOCKSOURCE.init_once(|| source);
+
+    let wall_clock = Duration::from_secs(rtc::read().timestamp());
+    BOOT_TIME.init_once(|| wall_clock.saturating_sub(uptime()));
+}
+
+fn tick(_vector: u8) -> IrqReturn {
+    task::timer::tick();
+    IrqReturn::Handled
+}
+
+pub fn clocksource() -> ClockSource {
+    CLOCKSOURCE.get().copied().unwrap_or(ClockSource::Pit)
+}
+
+/// Monotonic time since boot.
+pub fn uptime() -> Duration {
+    match clocksource() {
+        ClockSource::Hpet => hpet::elapsed().unwrap_or_default(),
+        ClockSource::Pit => ticks_to_duration(task::timer::ticks()),
+    }
+}
+
+/// Time since the Unix epoch, based on the RTC time read at boot.
+pub fn unix_time() -> Duration {
+    BOOT_TIME.get().copied().unwrap_or_default() + uptime()
+}
+
+/// Current wall-clock time in UTC.
+pub fn now() -> DateTime {
+    DateTime::from_timestamp(unix_time().as_secs())
+}
+
+pub fn ticks_to_duration(ticks: usize) -> Duration {
+    Duration::from_nanos(ticks as u64 * (1_000_000_000 / TICK_HZ))
+}
+
+/// Converts a duration to ticks, rounding up.
+pub fn duration_to_ticks(duration: Duration) -> usize {
+    let tick = 1_000_000_000 / u128::from(TICK_HZ);
+    ((duration.as_nanos() + tick - 1) / tick) as usize
+}
@@ -0,0 +1,20 @@
+use x86_64::instructions::port::Port;
+
+/// Input frequency of the PIT in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const CHANNEL_0: u16 = 0x40;
+const COMMAND: u16 = 0x43;
+
+/// Programs channel 0 to fire IRQ 0 with the given frequency.
+pub fn init(hz: u64) {
+    let divisor = (PIT_FREQUENCY / hz).clamp(1, 0xffff) as u16;
+    let mut command: Port<u8> = Port::new(COMMAND);
+    let mut channel: Port<u8> = Port::new(CHANNEL_0);
+    unsafe {
+        // channel 0, lobyte/hibyte access, mode 3 (square wave generator)
+        command.write(0b0011_0110);
+        channel.write((divisor & 0xff) as u8);
+        channel.write((divisor >> 8) as u8);
+    }
+}
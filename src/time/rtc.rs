@@ -0,0 +1,283 @@
+use crate::acpi;
+use crate::interrupts::{self, HandlerId, InterruptVectors, IrqReturn};
+use core::fmt;
+use crossbeam::atomic::AtomicCell;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0a;
+const STATUS_B: u8 = 0x0b;
+const STATUS_C: u8 = 0x0c;
+
+// status register bits
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const HOUR_FORMAT_24: u8 = 1 << 1;
+const BINARY_FORMAT: u8 = 1 << 2;
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+const PM: u8 = 1 << 7;
+
+/// Offset of the century register index in the ACPI FADT.
+const FADT_CENTURY: u64 = 108;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtcError {
+    /// Periodic rates range from 3 (8 kHz) to 15 (2 Hz).
+    InvalidRate,
+    /// IRQ 8 is routed to the HPET.
+    IrqInUse,
+}
+
+/// A calendar date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+static PERIODIC_HANDLER: AtomicCell<Option<fn()>> = AtomicCell::new(None);
+static IRQ_HANDLER: AtomicCell<Option<HandlerId>> = AtomicCell::new(None);
+
+fn read_register(register: u8) -> u8 {
+    let mut index: Port<u8> = Port::new(CMOS_INDEX);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    without_interrupts(|| unsafe {
+        index.write(register);
+        data.read()
+    })
+}
+
+fn write_register(register: u8, value: u8) {
+    let mut index: Port<u8> = Port::new(CMOS_INDEX);
+    let mut data: Port<u8> = Port::new(CMOS_DATA);
+    without_interrupts(|| unsafe {
+        index.write(register);
+        data.write(value);
+    });
+}
+
+fn century_register() -> Option<u8> {
+    let fadt = acpi::find_table(b"FACP")?;
+    let register: u8 = unsafe { acpi::read(fadt + FADT_CENTURY) };
+    (register != 0).then(|| register)
+}
+
+fn read_raw(century: Option<u8>) -> [u8; 7] {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+    [
+        read_register(SECONDS),
+        read_register(MINUTES),
+        read_register(HOURS),
+        read_register(DAY),
+        read_register(MONTH),
+        read_register(YEAR),
+        century.map_or(0, read_register),
+    ]
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Reads the current date and time from the CMOS clock.
+///
+/// Returns [`DateTime::EPOCH`] if the clock holds no valid date after 1970,
+/// for example after the CMOS was reset.
+pub fn read() -> DateTime {
+    let century_register = century_register();
+
+    // read until we get the same values twice, so an update in between
+    // two register reads can not tear the result
+    let mut raw = read_raw(century_register);
+    loop {
+        let next = read_raw(century_register);
+        if next == raw {
+            break;
+        }
+        raw = next;
+    }
+    let [second, minute, mut hour, day, month, year, century] = raw;
+
+    let status = read_register(STATUS_B);
+    let pm = hour & PM != 0;
+    hour &= !PM;
+    let convert = |value| {
+        if status & BINARY_FORMAT != 0 {
+            value
+        } else {
+            from_bcd(value)
+        }
+    };
+    let mut hour = convert(hour);
+    if status & HOUR_FORMAT_24 == 0 {
+        hour = match (hour, pm) {
+            (12, false) => 0,
+            (12, true) => 12,
+            (hour, true) => hour + 12,
+            (hour, false) => hour,
+        };
+    }
+    let century = match century_register {
+        Some(_) => u16::from(convert(century)),
+        None => 20,
+    };
+
+    let date_time = DateTime {
+        year: century * 100 + u16::from(convert(year)),
+        month: convert(month),
+        day: convert(day),
+        hour,
+        minute: convert(minute),
+        second: convert(second),
+    };
+    if date_time.is_valid() {
+        date_time
+    } else {
+        DateTime::EPOCH
+    }
+}
+
+/// Enables the periodic RTC interrupt on IRQ 8 with `32768 >> (rate - 1)` Hz.
+///
+/// The handler runs in interrupt context, so it must not block or allocate.
+pub fn enable_periodic(rate: u8, handler: fn()) -> Result<(), RtcError> {
+    if !(3..=15).contains(&rate) {
+        return Err(RtcError::InvalidRate);
+    }
+    without_interrupts(|| {
+        if super::hpet::is_tick_source() {
+            return Err(RtcError::IrqInUse);
+        }
+        PERIODIC_HANDLER.store(Some(handler));
+        let status_a = read_register(STATUS_A);
+        write_register(STATUS_A, (status_a & 0xf0) | rate);
+        let status_b = read_register(STATUS_B);
+        write_register(STATUS_B, status_b | PERIODIC_INTERRUPT);
+        // an unacknowledged interrupt would block all further ones
+        read_register(STATUS_C);
+        Ok(())
+    })?;
+    if IRQ_HANDLER.load().is_none() {
+        let id = interrupts::register_irq(InterruptVectors::Rtc.irq(), "rtc", handle_interrupt)
+            .expect("registering the RTC failed");
+        IRQ_HANDLER.store(Some(id));
+    }
+    Ok(())
+}
+
+/// Whether a periodic handler is installed, which keeps the HPET from
+/// taking over IRQ 8.
+pub fn is_periodic_enabled() -> bool {
+    PERIODIC_HANDLER.load().is_some()
+}
+
+pub fn disable_periodic() {
+    let status_b = read_register(STATUS_B);
+    write_register(STATUS_B, status_b & !PERIODIC_INTERRUPT);
+    PERIODIC_HANDLER.store(None);
+    if let Some(id) = IRQ_HANDLER.swap(None) {
+        interrupts::unregister_handler(id);
+    }
+}
+
+fn handle_interrupt(_vector: u8) -> IrqReturn {
+    if let Some(handler) = PERIODIC_HANDLER.load() {
+        // reading status C acknowledges the interrupt
+        if read_register(STATUS_C) & PERIODIC_INTERRUPT != 0 {
+            handler();
+            return IrqReturn::Handled;
+        }
+    }
+    IrqReturn::NotHandled
+}
+
+impl DateTime {
+    pub const EPOCH: DateTime = DateTime {
+        year: 1970,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+
+    /// Whether all fields are in range and the date is not before 1970.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=12).contains(&self.month)
+            && (1..=31).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Seconds since 1970-01-01 00:00:00 UTC, 0 for invalid dates.
+    pub fn timestamp(&self) -> u64 {
+        if !self.is_valid() {
+            return 0;
+        }
+        // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+        let (year, month) = if self.month <= 2 {
+            (u64::from(self.year) - 1, u64::from(self.month) + 9)
+        } else {
+            (u64::from(self.year), u64::from(self.month) - 3)
+        };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * month + 2) / 5 + u64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        days * 86400
+            + u64::from(self.hour) * 3600
+            + u64::from(self.minute) * 60
+            + u64::from(self.second)
+    }
+
+    pub fn from_timestamp(timestamp: u64) -> DateTime {
+        let seconds = timestamp % 86400;
+        let days = timestamp / 86400 + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let (year, month) = if month < 10 {
+            (year_of_era + era * 400, month + 3)
+        } else {
+            (year_of_era + era * 400 + 1, month - 9)
+        };
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
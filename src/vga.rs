@@ -1,7 +1,10 @@
+use crate::ps2::keyboard::{KeyCode, KeyEventStream, KeyState};
+use crate::sync::IrqSpinlock;
 use core::fmt;
 use core::fmt::Write;
+use crossbeam::atomic::AtomicCell;
+use futures::stream::StreamExt;
 use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
 use x86_64::instructions::port::PortWrite;
 
@@ -18,10 +21,7 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn _print(args: fmt::Arguments) {
-    // TODO: use Prologue / Epilogue
-    x86_64::instructions::interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
-    });
+    CONSOLES[SYSTEM_CONSOLE].lock().write_fmt(args).unwrap();
 }
 
 #[allow(dead_code)]
@@ -46,6 +46,30 @@ pub enum Color {
     White = 15,
 }
 
+/// The VGA colors in the order of the ANSI color numbers, the bright
+/// variants follow the normal ones.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightRed,
+    Color::LightGreen,
+    Color::Yellow,
+    Color::LightBlue,
+    Color::Pink,
+    Color::LightCyan,
+    Color::White,
+];
+// ANSI color numbers
+const DEFAULT_FOREGROUND: u8 = 2;
+const DEFAULT_BACKGROUND: u8 = 0;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
 struct ColorCode(u8);
@@ -62,76 +86,504 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+const BUFFER_HEIGHT: usize = 25;
+const BUFFER_WIDTH: usize = 80;
+/// Lines kept for scrolling back per console, the screen included.
+const HISTORY_LINES: usize = 200;
+pub const CONSOLE_COUNT: usize = 6;
+/// Boot messages, `print!` and the shell.
+pub const SYSTEM_CONSOLE: usize = 0;
+pub const LOG_CONSOLE: usize = 1;
+pub const DIAGNOSTICS_CONSOLE: usize = 2;
+const TAB_WIDTH: usize = 8;
+const MAX_PARAMETERS: usize = 4;
+/// Shown for characters the code page lacks.
+const UNPRINTABLE: u8 = 0xfe;
+
+type Line = [ScreenChar; BUFFER_WIDTH];
+
+const BLANK: ScreenChar = ScreenChar {
+    ascii_character: b' ',
+    color_code: ColorCode::new(Color::Green, Color::Black),
+};
+
+/// Only referenced by [`CONSOLES`].
+static mut HISTORY: [[Line; HISTORY_LINES]; CONSOLE_COUNT] =
+    [[[BLANK; BUFFER_WIDTH]; HISTORY_LINES]; CONSOLE_COUNT];
+static ACTIVE: AtomicCell<usize> = AtomicCell::new(SYSTEM_CONSOLE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    None,
+    Escape,
+    /// Inside a control sequence, `private` is set by a leading `?`.
+    Csi {
+        private: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Attributes {
+    foreground: u8,
+    background: u8,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Attributes {
+    const DEFAULT: Attributes = Attributes {
+        foreground: DEFAULT_FOREGROUND,
+        background: DEFAULT_BACKGROUND,
+        bold: false,
+        reverse: false,
+    };
+
+    fn color_code(&self) -> ColorCode {
+        let mut foreground = self.foreground;
+        if self.bold {
+            foreground |= 8;
+        }
+        let (foreground, background) = if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        };
+        ColorCode::new(
+            ANSI_COLORS[usize::from(foreground)],
+            ANSI_COLORS[usize::from(background)],
+        )
+    }
+}
+
+/// A text console that understands the common VT100 and ANSI escape
+/// sequences and keeps lines scrolled off the screen. Only the active
+/// console writes to the VGA buffer.
 pub struct Writer {
-    column_position: usize,
+    row: usize,
+    /// Reaches `BUFFER_WIDTH` after the last column was written, the line
+    /// wraps with the next character.
+    column: usize,
+    saved_cursor: (usize, usize),
+    cursor_visible: bool,
+    attributes: Attributes,
     color_code: ColorCode,
+    escape: EscapeState,
+    parameters: [u16; MAX_PARAMETERS],
+    parameter_count: usize,
+    /// Set once a sequence has more than `MAX_PARAMETERS` parameters, the
+    /// digits of the extra ones are ignored.
+    parameters_overflowed: bool,
+    /// Ring of lines, the screen starts at `top`.
+    history: &'static mut [Line; HISTORY_LINES],
+    top: usize,
+    /// Lines above the screen that can be scrolled back to.
+    scrollback: usize,
+    /// Lines the view is currently scrolled back.
+    view_offset: usize,
+    visible: bool,
     buffer: &'static mut Buffer,
 }
 
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Green, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref CONSOLES: [IrqSpinlock<Writer>; CONSOLE_COUNT] = {
+        let mut histories = unsafe { HISTORY.iter_mut() };
+        let mut consoles = [(); CONSOLE_COUNT].map(|_| {
+            IrqSpinlock::new(Writer {
+                row: 0,
+                column: 0,
+                saved_cursor: (0, 0),
+                cursor_visible: true,
+                attributes: Attributes::DEFAULT,
+                color_code: Attributes::DEFAULT.color_code(),
+                escape: EscapeState::None,
+                parameters: [0; MAX_PARAMETERS],
+                parameter_count: 0,
+                parameters_overflowed: false,
+                history: histories.next().unwrap(),
+                top: 0,
+                scrollback: 0,
+                view_offset: 0,
+                visible: false,
+                buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+            })
+        });
+        consoles[SYSTEM_CONSOLE].get_mut().visible = true;
+        consoles
+    };
 }
 
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+/// The console shown on the screen.
+pub fn active() -> usize {
+    ACTIVE.load()
+}
+
+/// Shows `console` and gives it the keyboard focus.
+pub fn switch(console: usize) {
+    if console >= CONSOLE_COUNT {
+        return;
+    }
+    let previous = ACTIVE.swap(console);
+    if previous != console {
+        CONSOLES[previous].lock().visible = false;
+        let mut writer = CONSOLES[console].lock();
+        writer.visible = true;
+        writer.redraw();
+    }
+}
+
+/// Releases the console locks and shows the system console, for crash
+/// reports.
+///
+/// # Safety
+///
+/// The code holding the locks must never run again.
+pub unsafe fn force_unlock() {
+    for console in CONSOLES.iter() {
+        console.force_unlock();
+    }
+    let mut writer = CONSOLES[SYSTEM_CONSOLE].lock();
+    // the crash may have cut off an escape sequence
+    writer.escape = EscapeState::None;
+    writer.set_attributes(Attributes::DEFAULT);
+    drop(writer);
+    switch(SYSTEM_CONSOLE);
+    CONSOLES[SYSTEM_CONSOLE].lock().scroll_to_bottom();
+}
+
+/// `fmt::Write` adapter for one of the [`CONSOLES`].
+pub struct Console(pub usize);
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        CONSOLES[self.0].lock().write_string(s);
+        Ok(())
+    }
+}
 
 impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
+    const fn new(foreground: Color, background: Color) -> ColorCode {
         ColorCode((background as u8) << 4 | (foreground as u8))
     }
 }
 
 impl Writer {
     pub fn write_byte(&mut self, byte: u8) {
-        let row = BUFFER_HEIGHT - 1;
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-                let col = self.column_position;
-                self.write_at(byte, row, col);
-                self.column_position += 1;
+        self.process(byte);
+        self.set_cursor();
+    }
+
+    pub fn write_string(&mut self, s: &str) {
+        for c in s.chars() {
+            if c.is_ascii() {
+                self.process(c as u8);
+            } else {
+                self.process(UNPRINTABLE);
             }
         }
         self.set_cursor();
     }
 
+    /// Writes a character without moving the cursor.
     pub fn write_at(&mut self, byte: u8, row: usize, column: usize) {
         let color_code = self.color_code;
-        self.buffer.chars[row][column].write(ScreenChar {
-            ascii_character: byte,
-            color_code,
-        });
+        self.put(
+            row,
+            column,
+            ScreenChar {
+                ascii_character: byte,
+                color_code,
+            },
+        );
+    }
+
+    /// Blanks the screen, the indicator in the top right corner included,
+    /// and moves the cursor home. The scrollback is kept.
+    pub fn clear(&mut self) {
+        self.erase_lines(0, BUFFER_HEIGHT);
+        self.row = 0;
+        self.column = 0;
+        self.scroll_to_bottom();
+    }
+
+    /// Shows older lines.
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.view_offset = (self.view_offset + lines).min(self.scrollback);
+        self.redraw();
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.view_offset = self.view_offset.saturating_sub(lines);
+        self.redraw();
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.view_offset = 0;
+        self.redraw();
+    }
+
+    fn process(&mut self, byte: u8) {
+        match self.escape {
+            EscapeState::None => self.process_plain(byte),
+            EscapeState::Escape => self.process_escape(byte),
+            EscapeState::Csi { private } => self.process_csi(byte, private),
+        }
+    }
+
+    fn process_plain(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.column = 0;
+                self.line_feed();
+            }
+            b'\r' => self.column = 0,
+            b'\t' => {
+                self.column = ((self.column / TAB_WIDTH + 1) * TAB_WIDTH).min(BUFFER_WIDTH - 1)
+            }
+            // move back, the next character overwrites the previous one
+            0x08 => self.column = self.column.min(BUFFER_WIDTH - 1).saturating_sub(1),
+            0x1b => self.escape = EscapeState::Escape,
+            // bell and other control characters
+            0x00..=0x1f => {}
+            byte => {
+                if self.column >= BUFFER_WIDTH {
+                    self.column = 0;
+                    self.line_feed();
+                }
+                self.write_at(byte, self.row, self.column);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn process_escape(&mut self, byte: u8) {
+        self.escape = EscapeState::None;
+        match byte {
+            b'[' => {
+                self.parameters = [0; MAX_PARAMETERS];
+                self.parameter_count = 0;
+                self.parameters_overflowed = false;
+                self.escape = EscapeState::Csi { private: false };
+            }
+            b'7' => self.saved_cursor = (self.row, self.column),
+            b'8' => (self.row, self.column) = self.saved_cursor,
+            b'c' => {
+                self.set_attributes(Attributes::DEFAULT);
+                self.cursor_visible = true;
+                self.clear();
+            }
+            _ => {}
+        }
+    }
+
+    fn process_csi(&mut self, byte: u8, private: bool) {
+        match byte {
+            b'?' if self.parameter_count == 0 => {
+                self.escape = EscapeState::Csi { private: true };
+            }
+            b'0'..=b'9' if !self.parameters_overflowed => {
+                if self.parameter_count == 0 {
+                    self.parameter_count = 1;
+                }
+                if let Some(parameter) = self.parameters.get_mut(self.parameter_count - 1) {
+                    *parameter = parameter
+                        .saturating_mul(10)
+                        .saturating_add(u16::from(byte - b'0'));
+                }
+            }
+            b'0'..=b'9' => {}
+            b';' if self.parameter_count.max(1) == MAX_PARAMETERS => {
+                self.parameters_overflowed = true;
+            }
+            b';' => self.parameter_count = self.parameter_count.max(1) + 1,
+            // intermediate bytes
+            0x20..=0x2f | b'<'..=b'?' => {}
+            byte => {
+                self.escape = EscapeState::None;
+                if private {
+                    self.private_sequence(byte);
+                } else {
+                    self.control_sequence(byte);
+                }
+            }
+        }
+    }
+
+    /// The parameter at `index`, with missing or zero values replaced by
+    /// `default`.
+    fn parameter(&self, index: usize, default: usize) -> usize {
+        match self.parameters[index] {
+            0 => default,
+            value => usize::from(value),
+        }
+    }
+
+    fn control_sequence(&mut self, command: u8) {
+        let count = self.parameter(0, 1);
+        let row = self.row;
+        let column = self.column.min(BUFFER_WIDTH - 1);
+        match command {
+            b'A' => self.row = row.saturating_sub(count),
+            b'B' => self.row = (row + count).min(BUFFER_HEIGHT - 1),
+            b'C' => self.column = (column + count).min(BUFFER_WIDTH - 1),
+            b'D' => self.column = column.saturating_sub(count),
+            b'E' => (self.row, self.column) = ((row + count).min(BUFFER_HEIGHT - 1), 0),
+            b'F' => (self.row, self.column) = (row.saturating_sub(count), 0),
+            b'G' => self.column = count.min(BUFFER_WIDTH) - 1,
+            b'd' => self.row = count.min(BUFFER_HEIGHT) - 1,
+            b'H' | b'f' => {
+                self.row = count.min(BUFFER_HEIGHT) - 1;
+                self.column = self.parameter(1, 1).min(BUFFER_WIDTH) - 1;
+            }
+            b'J' => self.erase_display(self.parameters[0]),
+            b'K' => self.erase_line(self.parameters[0]),
+            b'm' => self.select_graphic_rendition(),
+            b's' => self.saved_cursor = (row, self.column),
+            b'u' => (self.row, self.column) = self.saved_cursor,
+            _ => {}
+        }
     }
 
-    fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+    fn private_sequence(&mut self, command: u8) {
+        if self.parameters[0] == 25 {
+            match command {
+                b'h' => self.cursor_visible = true,
+                b'l' => self.cursor_visible = false,
+                _ => {}
             }
         }
-        let blank = ScreenChar {
+    }
+
+    fn erase_display(&mut self, mode: u16) {
+        let row = self.row;
+        match mode {
+            0 => {
+                self.erase_in_line(row, self.column, BUFFER_WIDTH);
+                self.erase_lines(row + 1, BUFFER_HEIGHT);
+            }
+            1 => {
+                self.erase_lines(0, row);
+                self.erase_in_line(row, 0, self.column + 1);
+            }
+            2 => self.erase_lines(0, BUFFER_HEIGHT),
+            3 => {
+                self.erase_lines(0, BUFFER_HEIGHT);
+                self.scrollback = 0;
+                self.scroll_to_bottom();
+            }
+            _ => {}
+        }
+    }
+
+    fn erase_line(&mut self, mode: u16) {
+        let row = self.row;
+        match mode {
+            0 => self.erase_in_line(row, self.column, BUFFER_WIDTH),
+            1 => self.erase_in_line(row, 0, self.column + 1),
+            2 => self.erase_in_line(row, 0, BUFFER_WIDTH),
+            _ => {}
+        }
+    }
+
+    fn select_graphic_rendition(&mut self) {
+        let mut attributes = self.attributes;
+        for &parameter in &self.parameters[..self.parameter_count.max(1)] {
+            match parameter {
+                0 => attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                22 => attributes.bold = false,
+                7 => attributes.reverse = true,
+                27 => attributes.reverse = false,
+                30..=37 => attributes.foreground = (parameter - 30) as u8,
+                39 => attributes.foreground = DEFAULT_FOREGROUND,
+                40..=47 => attributes.background = (parameter - 40) as u8,
+                49 => attributes.background = DEFAULT_BACKGROUND,
+                90..=97 => attributes.foreground = (parameter - 90) as u8 | 8,
+                100..=107 => attributes.background = (parameter - 100) as u8 | 8,
+                _ => {}
+            }
+        }
+        self.set_attributes(attributes);
+    }
+
+    fn set_attributes(&mut self, attributes: Attributes) {
+        self.attributes = attributes;
+        self.color_code = attributes.color_code();
+    }
+
+    fn blank(&self) -> ScreenChar {
+        ScreenChar {
             ascii_character: b' ',
             color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[BUFFER_HEIGHT - 1][col].write(blank);
         }
-        self.column_position = 0;
+    }
+
+    fn erase_in_line(&mut self, row: usize, start: usize, end: usize) {
+        let blank = self.blank();
+        for column in start..end.min(BUFFER_WIDTH) {
+            self.put(row, column, blank);
+        }
+    }
+
+    fn erase_lines(&mut self, start: usize, end: usize) {
+        for row in start..end {
+            self.erase_in_line(row, 0, BUFFER_WIDTH);
+        }
+    }
+
+    fn line(&mut self, row: usize) -> &mut Line {
+        &mut self.history[(self.top + row) % HISTORY_LINES]
+    }
+
+    /// Writes to the history and, unless scrolled back, to the screen.
+    fn put(&mut self, row: usize, column: usize, character: ScreenChar) {
+        self.line(row)[column] = character;
+        if self.visible && self.view_offset == 0 {
+            self.buffer.chars[row][column].write(character);
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < BUFFER_HEIGHT {
+            self.row += 1;
+            return;
+        }
+        self.top = (self.top + 1) % HISTORY_LINES;
+        self.scrollback = (self.scrollback + 1).min(HISTORY_LINES - BUFFER_HEIGHT);
+        let blank = self.blank();
+        *self.line(BUFFER_HEIGHT - 1) = [blank; BUFFER_WIDTH];
+        // keep showing the same lines while scrolled back
+        if self.view_offset != 0 && self.view_offset < self.scrollback {
+            self.view_offset += 1;
+        } else {
+            self.redraw();
+        }
+    }
+
+    fn redraw(&mut self) {
+        if !self.visible {
+            return;
+        }
+        for row in 0..BUFFER_HEIGHT {
+            let index = (self.top + HISTORY_LINES + row - self.view_offset) % HISTORY_LINES;
+            for column in 0..BUFFER_WIDTH {
+                self.buffer.chars[row][column].write(self.history[index][column]);
+            }
+        }
+        self.set_cursor();
     }
 
     fn set_cursor(&self) {
-        let row = BUFFER_HEIGHT - 1;
+        if !self.visible {
+            return;
+        }
         let index_port = 0x3d4;
         let data_port = 0x3d5;
-        let pos = row * BUFFER_WIDTH + self.column_position;
+        // a position past the screen hides the cursor
+        let pos = if self.cursor_visible && self.view_offset == 0 {
+            self.row * BUFFER_WIDTH + self.column.min(BUFFER_WIDTH - 1)
+        } else {
+            BUFFER_HEIGHT * BUFFER_WIDTH
+        };
         let low: u8 = (pos & 0xff).try_into().unwrap();
         let high: u8 = (pos >> 8).try_into().unwrap();
         unsafe {
@@ -146,17 +598,6 @@ impl Writer {
             u8::write_to_port(data_port, low);
         }
     }
-
-    pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                b'\r' => self.column_position = 0,
-                // not part of printable ASCII range
-                b => self.write_byte(b),
-            }
-        }
-    }
 }
 
 impl fmt::Write for Writer {
@@ -165,3 +606,36 @@ impl fmt::Write for Writer {
         Ok(())
     }
 }
+
+/// Switches consoles with Alt+F1..F6 and scrolls through the history of
+/// the active one with Shift+PgUp and Shift+PgDn, back to the bottom when
+/// typing.
+pub async fn handle_keys() {
+    let mut keys = KeyEventStream::new();
+    while let Some(event) = keys.next().await {
+        if event.state != KeyState::Down {
+            continue;
+        }
+        let console = match event.code {
+            KeyCode::F1 => Some(0),
+            KeyCode::F2 => Some(1),
+            KeyCode::F3 => Some(2),
+            KeyCode::F4 => Some(3),
+            KeyCode::F5 => Some(4),
+            KeyCode::F6 => Some(5),
+            _ => None,
+        };
+        if let Some(console) = console.filter(|_| event.modifiers.alt) {
+            switch(console);
+            continue;
+        }
+        let shift = event.modifiers.shift();
+        let mut writer = CONSOLES[active()].lock();
+        match event.code {
+            KeyCode::PageUp if shift => writer.scroll_up(BUFFER_HEIGHT / 2),
+            KeyCode::PageDown if shift => writer.scroll_down(BUFFER_HEIGHT / 2),
+            _ if event.unicode.is_some() && writer.view_offset != 0 => writer.scroll_to_bottom(),
+            _ => {}
+        }
+    }
+}